@@ -1,15 +1,27 @@
 mod modules;
 
-use std::collections::HashMap;
-use modules::load_balancer::{LoadBalancer, Protocol};
-use modules::handlers::{handle_tcp, handle_udp};
-use modules::utils::{log, print_help, parse_arguments};
-use modules::dns::resolve_ring_domain;
+use modules::load_balancer::{load_drain_state, LoadBalancer, Protocol};
+use modules::handlers::{handle_tcp, handle_udp, TcpConfig, UdpConfig};
+use modules::logging::{init_logging, log, log_error, log_warn};
+use modules::utils::{print_help, parse_arguments, shutdown_signal, with_protocols};
+use modules::reload::reload_on_sighup;
+use modules::dns::{next_ring_refresh, refresh_ring_domain, HostBackend, RdnsCache, RingConfig, RingSource};
+use modules::config::{resolve_arguments, FileConfig};
+use modules::metrics::run_metrics_server;
+use modules::http_health::run_http_health_server;
+use modules::sockets::{bind_error_message, bind_tcp_listeners, bind_with_retry};
+use modules::pool::{ConnectionPool, PoolConfig};
+use modules::rate_limit::ConnRateLimiter;
+#[cfg(feature = "tls")]
+use modules::tls::load_tls_acceptor;
+use modules::uds::{perform_uds_health_check, remove_uds_socket, run_uds_status_server, UdsConfig, DEFAULT_UDS_PATH};
 use std::env;
-use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::net::{TcpListener, UdpSocket};
-use tokio::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::{watch, Semaphore};
+
+const EXIT_BIND_FAILED: i32 = 3; // The listen address could not be bound, configuration errors exit with 2
+const EXIT_NO_BACKENDS: i32 = 4; // Nothing the backends are resolved from gave an address at startup
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -20,47 +32,232 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         return Ok(());
     }
 
+    if args.contains(&String::from("--version")) || args.contains(&String::from("-V")) {
+        println!("SideLB {}", env!("CARGO_PKG_VERSION"));
+        return Ok(());
+    }
+
+    // Query a running instance over its UDS status socket, used as container health check.
+    // An argument after the flag names the socket, for instances started with uds_path=, and
+    // --min-active=<n> fails the check while fewer backends are active. --uds-token=<secret> or
+    // SIDELB_UDS_TOKEN authenticates that query against a server with uds_token_reads=true
+    if let Some(pos) = args.iter().position(|arg| arg == "--health-check-uds") {
+        let path = args[pos + 1..].iter().find(|arg| !arg.starts_with('-')).map_or(DEFAULT_UDS_PATH, String::as_str);
+        let min_active = match args.iter().find_map(|arg| arg.strip_prefix("--min-active=")) {
+            Some(value) => match value.parse() {
+                Ok(min_active) => Some(min_active),
+                Err(_) => {
+                    eprintln!("Invalid --min-active value: {}", value);
+                    std::process::exit(2);
+                }
+            },
+            None => None,
+        };
+        let token = args
+            .iter()
+            .find_map(|arg| arg.strip_prefix("--uds-token="))
+            .map(str::to_string)
+            .or_else(|| std::env::var("SIDELB_UDS_TOKEN").ok().filter(|token| !token.is_empty()));
+        std::process::exit(perform_uds_health_check(path, min_active, token.as_deref()).await);
+    }
+
+    // --dump-config prints the effective settings as a config file instead of starting
+    let dump_config = args.iter().any(|arg| arg == "--dump-config");
+    let args: Vec<String> = args.into_iter().filter(|arg| arg != "--dump-config").collect();
+
+    // Merge a config file with the command line if one was given
+    let cli_args = match resolve_arguments(&args[1..]) {
+        Ok(cli_args) => cli_args,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
     // Parse arguments and determine protocol
-    let (bind_addr, backend_addrs, ring_domain, mode, proto) = parse_arguments(&args[1..]);
+    let config = match parse_arguments(&cli_args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            eprintln!("Run 'sidelb --help' for usage.");
+            std::process::exit(2);
+        }
+    };
+    if dump_config {
+        match toml::to_string(&FileConfig::from_config(&config)) {
+            Ok(dump) => {
+                print!("{}", dump);
+                return Ok(());
+            }
+            Err(e) => {
+                eprintln!("Failed to write the configuration as TOML: {}", e);
+                std::process::exit(2);
+            }
+        }
+    }
+    if let Err(e) = init_logging(config.log.clone()) {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    }
+    if !config.log.stdout && config.log.file.is_none() {
+        eprintln!("Warning: log_stdout=false without log_file= discards all log output");
+    }
+    let mode = config.mode;
+    let protocols = config.protocols.clone();
+    let bind_list = config.bind_addrs.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ");
 
     log(format!(
         "Starting load balancer on address: {} with protocol: {:?} and mode: {:?}",
-        bind_addr, proto, mode
+        bind_list, protocols, mode
     ));
 
-    let lb = Arc::new(LoadBalancer::new(mode));
+    // Load the TLS certificate up front so a broken setup fails before any listener starts
+    #[cfg(feature = "tls")]
+    let tls_acceptor = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => match load_tls_acceptor(cert, key) {
+            Ok(acceptor) => {
+                log(format!("Terminating TLS with certificate {}", cert));
+                Some(acceptor)
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(2);
+            }
+        },
+        (None, None) => None,
+        _ => {
+            eprintln!("TLS termination needs both tls_cert= and tls_key=");
+            std::process::exit(2);
+        }
+    };
+    #[cfg(not(feature = "tls"))]
+    if config.tls_cert.is_some() || config.tls_key.is_some() {
+        eprintln!("TLS termination is not available, SideLB was built without the tls feature");
+        std::process::exit(2);
+    }
 
-    // Add backend addresses provided directly
-    let mut backends_with_protocol = HashMap::new();
-    for (hostname, ips) in backend_addrs {
-        let backend_list: Vec<(SocketAddr, Option<Protocol>)> = ips
-            .into_iter()
-            .map(|addr| (addr, Some(proto))) // Use the provided protocol
-            .collect();
-        backends_with_protocol.insert(hostname, backend_list);
-    }
-    lb.add_backends(backends_with_protocol).await;
-
-    // If a ring domain is provided, resolve and add its backends
-    if let Some(ring_domain) = ring_domain {
-        log(format!("Resolving ring address: {}", ring_domain));
-        let resolved_backends = resolve_ring_domain(&ring_domain, proto).await;
-
-        if resolved_backends.is_empty() {
-            eprintln!("Failed to resolve ring domain or no backends found.");
-            return Ok(()); // Exit the program if no backends are found
+    #[cfg(not(feature = "grpc"))]
+    if config.health.grpc_service.is_some() {
+        eprintln!("gRPC health checks are not available, SideLB was built without the grpc feature");
+        std::process::exit(2);
+    }
+
+    // Bind the listeners before any background task runs, so a failure leaves nothing behind
+    let mut tcp_listeners = Vec::new();
+    let mut udp_sockets = Vec::new();
+    for &bind_addr in &config.bind_addrs {
+        if protocols.contains(&Protocol::TCP) {
+            match bind_with_retry("TCP", bind_addr, config.bind_retry, || bind_tcp_listeners(bind_addr, config.workers, config.reuse_addr)).await {
+                Ok(listeners) => tcp_listeners.push((bind_addr, listeners)),
+                Err(e) => {
+                    log_error(bind_error_message("TCP", bind_addr, &e));
+                    std::process::exit(EXIT_BIND_FAILED);
+                }
+            }
+        }
+        if protocols.contains(&Protocol::UDP) {
+            match bind_with_retry("UDP", bind_addr, config.bind_retry, || UdpSocket::bind(bind_addr)).await {
+                Ok(socket) => udp_sockets.push((bind_addr, Arc::new(socket))),
+                Err(e) => {
+                    log_error(bind_error_message("UDP", bind_addr, &e));
+                    std::process::exit(EXIT_BIND_FAILED);
+                }
+            }
         }
+    }
 
-        let mut resolved_groups: HashMap<String, Vec<(SocketAddr, Option<Protocol>)>> = HashMap::new();
-        for (addr, detected_protocol) in resolved_backends {
-            let host = addr.ip().to_string();
-            resolved_groups
-                .entry(host)
-                .or_insert_with(Vec::new)
-                .push((addr, Some(detected_protocol.unwrap_or(proto))));
+    let mut lb = LoadBalancer::new(mode, config.health.clone());
+    lb.max_conns = config.max_conns;
+    lb.backend_max_conns = config.backend_max_conns.clone();
+    lb.group_tiers = config.group_tiers.clone();
+    lb.group_modes = config.group_modes.clone();
+    lb.rr_scope = config.rr_scope;
+    lb.global_conns = config.max_global_conns.map(|limit| Arc::new(Semaphore::new(limit)));
+    lb.conn_rate = config.conn_rate.map(ConnRateLimiter::new);
+    lb.slow_start = config.slow_start;
+    lb.source_addr = config.source_addr;
+    lb.adaptive_latency_weight = config.adaptive_latency_weight;
+    lb.drain_timeout = config.drain_timeout;
+    lb.drain_state = config.drain_state.clone();
+    lb.wait_for_health = config.wait_for_health;
+    lb.strict_health = config.strict_health;
+    lb.ring_groups = config.ring_domains.iter()
+        .chain(&config.ring_srvs)
+        .chain(config.host_backends.keys())
+        .cloned()
+        .collect();
+    // Restore drains before any backend is added, so a drained backend never gets traffic
+    if let Some(path) = &config.drain_state {
+        match load_drain_state(path) {
+            Ok(drained) => {
+                if !drained.is_empty() {
+                    log(format!("Restored {} drained backends from {}", drained.len(), path.display()));
+                }
+                *lb.admin_drained.get_mut() = drained;
+            }
+            Err(e) => {
+                eprintln!("Failed to read drain state {}: {}", path.display(), e);
+                std::process::exit(2);
+            }
         }
+    }
+    let lb = Arc::new(lb);
+
+    // Add backend addresses provided directly
+    lb.add_backends(with_protocols(config.backends.clone(), &protocols)).await;
+
+    // Resolve every ring domain (and every group of host name backends) into its own group and keep it fresh in the background
+    let ring = RingConfig {
+        protocols: protocols.clone(),
+        ip_family: config.ip_family,
+        max_interval: config.ring_interval,
+        jitter: config.timer_jitter,
+        rdns: config.rdns.then(|| Arc::new(RdnsCache::new(config.rdns_cache_ttl))),
+        keep_last: config.ring_keep_last,
+    };
+    let mut host_groups: Vec<(String, Vec<HostBackend>)> = config.host_backends.clone().into_iter().collect();
+    host_groups.sort_by(|a, b| a.0.cmp(&b.0));
+    let ring_sources: Vec<RingSource> = config.ring_domains.iter().cloned().map(RingSource::Domain)
+        .chain(config.ring_srvs.iter().cloned().map(RingSource::Srv))
+        .chain(host_groups.into_iter().map(|(label, hosts)| RingSource::Hosts(label, hosts)))
+        .collect();
+    if !ring_sources.is_empty() {
+        log(format!(
+            "Ring domains are re-resolved when their DNS TTL expires, at most every {} seconds",
+            ring.max_interval.as_secs()
+        ));
+    }
+    let mut unresolved = Vec::new();
+    for source in ring_sources.iter().cloned() {
+        log(format!("Resolving ring address: {}", source.name()));
+        let ttl = match source.resolve(&ring).await {
+            Ok((resolved_backends, ttl)) => {
+                if resolved_backends.is_empty() {
+                    log_warn(format!("No backends found for {}.", source.describe()));
+                    unresolved.push(source.describe());
+                } else {
+                    lb.update_dynamic_backends(source.name(), resolved_backends).await;
+                }
+                ttl
+            }
+            Err(e) => {
+                log_warn(format!("Failed to resolve {}: {}", source.describe(), e));
+                unresolved.push(source.describe());
+                None
+            }
+        };
+
+        let ring_lb = lb.clone();
+        let ring = ring.clone();
+        let first_delay = next_ring_refresh(ttl, ring.max_interval);
+        tokio::spawn(async move {
+            refresh_ring_domain(ring_lb, source, ring, first_delay).await;
+        });
+    }
 
-        lb.add_backends(resolved_groups).await;
+    if !ring_sources.is_empty() && lb.backends.lock().await.is_empty() {
+        log_error(format!("No backends found, could not resolve {}", unresolved.join("; ")));
+        std::process::exit(EXIT_NO_BACKENDS);
     }
 
     // Start the health check task
@@ -69,38 +266,168 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         lb_clone.perform_health_checks().await;
     });
 
-    // Start the appropriate listener (TCP or UDP) based on the protocol selected
-    match proto {
-        Protocol::TCP => {
-            let tcp_listener = TcpListener::bind(bind_addr).await?;
-            let tcp_lb = lb.clone();
-            log(format!("TCP listener started on: {}", bind_addr));
-            tokio::spawn(async move {
-                loop {
-                    match tcp_listener.accept().await {
-                        Ok((inbound, _)) => {
-                            let tcp_lb = tcp_lb.clone();
-                            tokio::spawn(async move {
-                                handle_tcp(inbound, tcp_lb).await;
-                            });
-                        }
-                        Err(e) => eprintln!("Failed to accept TCP connection: {:?}", e),
+    // Remove backends that finished draining
+    let reaper_lb = lb.clone();
+    tokio::spawn(async move {
+        reaper_lb.reap_removed_backends().await;
+    });
+
+    // Apply static backend changes from the arguments or config file on SIGHUP
+    let reload_lb = lb.clone();
+    let reload_args = args[1..].to_vec();
+    let reload_protocols = protocols.clone();
+    tokio::spawn(async move {
+        reload_on_sighup(reload_lb, reload_args, cli_args, reload_protocols).await;
+    });
+
+    // Start the UDS status server
+    let uds_lb = lb.clone();
+    let uds = UdsConfig {
+        path: config.uds_path.clone(),
+        mode: config.uds_mode,
+        owner: config.uds_owner,
+        group: config.uds_group,
+        token: config.uds_token.clone(),
+        token_reads: config.uds_token_reads,
+    };
+    tokio::spawn(async move {
+        run_uds_status_server(uds, uds_lb).await;
+    });
+
+    // Start the metrics endpoint if requested
+    if let Some(metrics_addr) = config.metrics_addr {
+        let metrics_lb = lb.clone();
+        tokio::spawn(async move {
+            run_metrics_server(metrics_addr, metrics_lb).await;
+        });
+    }
+
+    // Start the HTTP health endpoint if requested
+    if let Some(http_health_addr) = config.http_health_addr {
+        let health_lb = lb.clone();
+        tokio::spawn(async move {
+            run_http_health_server(http_health_addr, health_lb).await;
+        });
+    }
+
+    // Hold back traffic until health checks confirmed a backend, instead of trusting the configured list
+    if config.wait_for_health {
+        log("Waiting for the first health check to find an active backend before accepting traffic".to_string());
+        tokio::select! {
+            _ = lb.wait_until_ready() => log("Health check found an active backend, accepting traffic".to_string()),
+            _ = shutdown_signal() => {
+                log("Shutdown requested before any backend became active, exiting".to_string());
+                remove_uds_socket(&config.uds_path);
+                return Ok(());
+            }
+        }
+    }
+
+    // Signals the listeners to stop accepting new traffic
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
+    // Start the appropriate listeners (TCP and/or UDP) based on the protocols selected
+    for &proto in &protocols {
+        match proto {
+            Protocol::TCP => {
+                let tcp_listeners = std::mem::take(&mut tcp_listeners);
+                let pool = config.tcp_pool_size.map(|size| {
+                    Arc::new(ConnectionPool::new(PoolConfig {
+                        size,
+                        idle_timeout: config.tcp_pool_idle,
+                        connect_timeout: config.connect_timeout,
+                        source_addr: config.source_addr,
+                        nodelay: config.tcp_nodelay,
+                        keepalive: config.tcp_keepalive,
+                    }))
+                });
+                if let Some(pool) = pool.clone() {
+                    tokio::spawn(async move {
+                        pool.evict_idle().await;
+                    });
+                }
+                let tcp_config = Arc::new(TcpConfig {
+                    idle_timeout: config.idle_timeout,
+                    proxy_protocol: config.proxy_protocol,
+                    sni_routing: config.sni_routing,
+                    retries: config.retries,
+                    connect_timeout: config.connect_timeout,
+                    no_backend_wait: config.no_backend_wait,
+                    pool,
+                    access_log: config.access_log,
+                    nodelay: config.tcp_nodelay,
+                    keepalive: config.tcp_keepalive,
+                    #[cfg(feature = "tls")]
+                    tls_acceptor: tls_acceptor.clone(),
+                });
+                for (bind_addr, listeners) in tcp_listeners {
+                    if config.workers > 1 {
+                        log(format!("TCP listener started on: {} ({} workers)", bind_addr, config.workers));
+                    } else {
+                        log(format!("TCP listener started on: {}", bind_addr));
+                    }
+                    // One accept loop per listener, the kernel spreads connections between them
+                    for tcp_listener in listeners {
+                        let tcp_lb = lb.clone();
+                        let tcp_config = tcp_config.clone();
+                        let mut shutdown_rx = shutdown_rx.clone();
+                        tokio::spawn(async move {
+                            loop {
+                                tokio::select! {
+                                    accepted = tcp_listener.accept() => match accepted {
+                                        Ok((inbound, _)) => {
+                                            let tcp_lb = tcp_lb.clone();
+                                            let tcp_config = tcp_config.clone();
+                                            tokio::spawn(async move {
+                                                handle_tcp(inbound, tcp_lb, tcp_config).await;
+                                            });
+                                        }
+                                        Err(e) => log_warn(format!("Failed to accept TCP connection: {:?}", e)),
+                                    },
+                                    _ = shutdown_rx.changed() => break,
+                                }
+                            }
+                            log(format!("TCP listener on {} stopped accepting connections", bind_addr));
+                        });
                     }
                 }
-            });
-        }
-        Protocol::UDP => {
-            let udp_socket = Arc::new(UdpSocket::bind(bind_addr).await?);
-            let udp_lb = lb.clone();
-            log(format!("UDP listener started on: {}", bind_addr));
-            tokio::spawn(async move {
-                handle_udp(udp_socket, udp_lb).await;
-            });
+            }
+            Protocol::UDP => {
+                let udp_config = UdpConfig {
+                    session_timeout: config.udp_session_timeout,
+                    batch_size: config.udp_batch,
+                    max_datagram: config.udp_max_datagram,
+                    max_sessions: config.udp_max_sessions,
+                };
+                for (bind_addr, udp_socket) in std::mem::take(&mut udp_sockets) {
+                    let udp_lb = lb.clone();
+                    let udp_config = udp_config.clone();
+                    let shutdown_rx = shutdown_rx.clone();
+                    log(format!("UDP listener started on: {}", bind_addr));
+                    tokio::spawn(async move {
+                        handle_udp(udp_socket, udp_lb, udp_config, shutdown_rx).await;
+                        log(format!("UDP listener on {} stopped receiving packets", bind_addr));
+                    });
+                }
+            }
         }
     }
 
-    // Keep the main task alive
-    loop {
-        tokio::time::sleep(Duration::from_secs(3600)).await;
+    // Run until SIGTERM/SIGINT, then drain in-flight connections before exiting
+    shutdown_signal().await;
+    log(format!(
+        "Shutdown requested, draining active connections for up to {} seconds",
+        config.drain_timeout.as_secs()
+    ));
+    lb.start_draining();
+    let _ = shutdown_tx.send(true);
+
+    if lb.wait_for_drain(config.drain_timeout).await {
+        log("All connections drained, exiting".to_string());
+    } else {
+        log(format!("Drain timeout reached with {} active connections, exiting", lb.active_connections().await));
     }
+    remove_uds_socket(&config.uds_path);
+
+    Ok(())
 }
@@ -0,0 +1,377 @@
+use serde::{Deserialize, Serialize};
+use crate::modules::load_balancer::Protocol;
+use crate::modules::utils::{escape_bytes, Config};
+
+// Layout of the TOML configuration file, every key mirrors a command-line argument
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    pub bind: Option<String>,
+    pub mode: Option<String>,
+    pub group_mode: Option<String>,
+    pub rr_scope: Option<String>,
+    pub proto: Option<String>,
+    pub ring_domain: Option<String>,
+    pub ring_srv: Option<String>,
+    pub ring_interval: Option<u64>,
+    pub ring_keep_last: Option<bool>,
+    pub timer_jitter: Option<u8>,
+    pub rdns: Option<bool>,
+    pub rdns_cache_ttl: Option<u64>,
+    pub ip_family: Option<String>,
+    pub backends: Option<Vec<FileBackend>>,
+    pub health_interval: Option<u64>,
+    pub health_timeout: Option<u64>,
+    pub health_concurrency: Option<usize>,
+    pub health_http_path: Option<String>,
+    pub health_grpc: Option<String>,
+    pub udp_probe: Option<String>,
+    pub udp_expect: Option<String>,
+    pub rise: Option<u32>,
+    pub fall: Option<u32>,
+    pub passive_failures: Option<u32>,
+    pub passive_window: Option<u64>,
+    pub outlier_5xx_ratio: Option<u8>,
+    pub outlier_window: Option<u64>,
+    pub outlier_cooldown: Option<u64>,
+    pub circuit_failures: Option<u32>,
+    pub circuit_cooldown: Option<u64>,
+    pub idle_timeout: Option<u64>,
+    pub proxy_protocol: Option<String>,
+    pub sni_routing: Option<bool>,
+    pub retries: Option<u32>,
+    pub connect_timeout: Option<u64>,
+    pub no_backend_wait: Option<u64>,
+    pub tcp_pool_size: Option<usize>,
+    pub tcp_pool_idle: Option<u64>,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    pub udp_session_timeout: Option<u64>,
+    pub udp_response_timeout: Option<u64>,  // Deprecated, accepted with a warning
+    pub udp_response_idle_ms: Option<u64>,  // Deprecated, accepted with a warning
+    pub udp_batch: Option<usize>,
+    pub udp_max_datagram: Option<usize>,
+    pub udp_max_sessions: Option<usize>,
+    pub drain_timeout: Option<u64>,
+    pub drain_state: Option<String>,
+    pub uds_path: Option<String>,
+    pub uds_mode: Option<String>,
+    pub uds_owner: Option<String>,
+    pub uds_group: Option<String>,
+    pub uds_token: Option<String>,
+    pub uds_token_reads: Option<bool>,
+    pub metrics_addr: Option<String>,
+    pub http_health_addr: Option<String>,
+    pub log_format: Option<String>,
+    pub log_level: Option<String>,
+    pub access_log: Option<bool>,
+    pub log_file: Option<String>,
+    pub log_stdout: Option<bool>,
+    pub log_max_size: Option<u64>,
+    pub max_conns: Option<usize>,
+    pub max_global_conns: Option<usize>,
+    pub conn_rate: Option<u32>,
+    pub slow_start: Option<u64>,
+    pub workers: Option<usize>,
+    pub bind_retry: Option<u64>,
+    pub reuse_addr: Option<bool>,
+    pub wait_for_health: Option<bool>,
+    pub strict_health: Option<bool>,
+    pub tcp_nodelay: Option<bool>,
+    pub tcp_keepalive: Option<u64>,
+    pub source_addr: Option<String>,
+    pub adaptive_latency_weight: Option<u8>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileBackend {
+    pub group: Option<String>,
+    pub addr: String,
+    pub protocol: Option<String>,
+    pub weight: Option<u32>,
+    pub max_conns: Option<usize>,
+    pub tier: Option<u32>,
+}
+
+impl FileConfig {
+    // The effective settings of a parsed configuration, defaults included, for --dump-config.
+    // Loading the result with --config gives the same configuration again.
+    pub fn from_config(config: &Config) -> FileConfig {
+        let seconds = |duration: std::time::Duration| duration.as_secs();
+        let percent = |share: f64| (share * 100.0).round() as u8;
+        let join = |items: Vec<String>| Some(items.join(",")).filter(|joined| !joined.is_empty());
+
+        let mut labels: Vec<&String> = config.backends.keys().collect();
+        labels.sort();
+        let mut backends: Vec<FileBackend> = labels
+            .into_iter()
+            .flat_map(|label| config.backends[label].iter().map(move |entry| (label, entry)))
+            .map(|(label, (addr, protocol, weight))| FileBackend {
+                // Backends without a label are grouped by their IP, the group needs no mention then
+                group: Some(label.clone()).filter(|label| *label != addr.ip().to_string()),
+                addr: addr.to_string(),
+                protocol: protocol.map(|protocol| format!("{:?}", protocol).to_lowercase()),
+                weight: Some(*weight),
+                max_conns: config.backend_max_conns.get(addr).copied(),
+                tier: config.group_tiers.get(label).copied(),
+            })
+            .collect();
+        let mut host_labels: Vec<&String> = config.host_backends.keys().collect();
+        host_labels.sort();
+        for label in host_labels {
+            backends.extend(config.host_backends[label].iter().map(|host| FileBackend {
+                group: Some(label.clone()).filter(|label| *label != host.host),
+                addr: format!("{}:{}", host.host, host.port),
+                protocol: host.protocol.map(|protocol| format!("{:?}", protocol).to_lowercase()),
+                weight: Some(host.weight),
+                max_conns: None,
+                tier: config.group_tiers.get(label).copied(),
+            }));
+        }
+        let mut group_modes: Vec<String> = config.group_modes.iter().map(|(group, mode)| format!("{}:{}", group, mode)).collect();
+        group_modes.sort();
+        let protocols = config.protocols.iter().map(|protocol| match protocol {
+            Protocol::TCP => "tcp".to_string(),
+            Protocol::UDP => "udp".to_string(),
+        });
+
+        FileConfig {
+            bind: join(config.bind_addrs.iter().map(|addr| addr.to_string()).collect()),
+            mode: Some(config.mode.to_string()),
+            group_mode: join(group_modes),
+            rr_scope: Some(config.rr_scope.to_string()),
+            proto: join(protocols.collect()),
+            ring_domain: join(config.ring_domains.clone()),
+            ring_srv: join(config.ring_srvs.clone()),
+            ring_interval: Some(seconds(config.ring_interval)),
+            ring_keep_last: Some(config.ring_keep_last),
+            timer_jitter: Some(percent(config.timer_jitter)),
+            rdns: Some(config.rdns),
+            rdns_cache_ttl: Some(seconds(config.rdns_cache_ttl)),
+            ip_family: Some(config.ip_family.to_string()),
+            backends: Some(backends).filter(|backends| !backends.is_empty()),
+            health_interval: Some(seconds(config.health.interval)),
+            health_timeout: Some(seconds(config.health.timeout)),
+            health_concurrency: Some(config.health.concurrency),
+            health_http_path: config.health.http_path.clone(),
+            health_grpc: config.health.grpc_service.clone(),
+            udp_probe: config.health.udp_payload.as_deref().map(escape_bytes),
+            udp_expect: config.health.udp_expect.as_deref().map(escape_bytes),
+            rise: Some(config.health.rise),
+            fall: Some(config.health.fall),
+            passive_failures: Some(config.health.passive_failures),
+            passive_window: Some(seconds(config.health.passive_window)),
+            // 0 turns outlier detection off, which the argument does not accept
+            outlier_5xx_ratio: Some(percent(config.health.outlier_5xx_ratio)).filter(|ratio| *ratio > 0),
+            outlier_window: Some(seconds(config.health.outlier_window)),
+            outlier_cooldown: Some(seconds(config.health.outlier_cooldown)),
+            circuit_failures: Some(config.health.circuit_failures),
+            circuit_cooldown: Some(seconds(config.health.circuit_cooldown)),
+            idle_timeout: config.idle_timeout.map(seconds),
+            proxy_protocol: config.proxy_protocol.map(|version| version.to_string()),
+            sni_routing: Some(config.sni_routing),
+            retries: Some(config.retries),
+            connect_timeout: Some(config.connect_timeout.as_millis() as u64),
+            no_backend_wait: Some(config.no_backend_wait.map_or(0, |wait| wait.as_millis() as u64)),
+            tcp_pool_size: Some(config.tcp_pool_size.unwrap_or(0)),
+            tcp_pool_idle: Some(seconds(config.tcp_pool_idle)),
+            tls_cert: config.tls_cert.clone(),
+            tls_key: config.tls_key.clone(),
+            udp_session_timeout: Some(seconds(config.udp_session_timeout)),
+            udp_response_timeout: None,
+            udp_response_idle_ms: None,
+            udp_batch: Some(config.udp_batch),
+            udp_max_datagram: Some(config.udp_max_datagram),
+            udp_max_sessions: config.udp_max_sessions,
+            drain_timeout: Some(seconds(config.drain_timeout)),
+            drain_state: config.drain_state.as_ref().map(|path| path.display().to_string()),
+            uds_path: Some(config.uds_path.clone()),
+            uds_mode: Some(format!("{:04o}", config.uds_mode)),
+            uds_owner: config.uds_owner.map(|uid| uid.to_string()),
+            uds_group: config.uds_group.map(|gid| gid.to_string()),
+            uds_token: config.uds_token.clone(),
+            uds_token_reads: Some(config.uds_token_reads),
+            metrics_addr: config.metrics_addr.map(|addr| addr.to_string()),
+            http_health_addr: config.http_health_addr.map(|addr| addr.to_string()),
+            log_format: Some(config.log.format.to_string()),
+            log_level: Some(config.log.level.to_string()),
+            access_log: Some(config.access_log),
+            log_file: config.log.file.clone(),
+            log_stdout: Some(config.log.stdout),
+            log_max_size: Some(config.log.max_size),
+            max_conns: config.max_conns,
+            max_global_conns: config.max_global_conns,
+            conn_rate: config.conn_rate,
+            slow_start: Some(config.slow_start.map_or(0, seconds)),
+            workers: Some(config.workers),
+            bind_retry: Some(seconds(config.bind_retry)),
+            reuse_addr: Some(config.reuse_addr),
+            wait_for_health: Some(config.wait_for_health),
+            strict_health: Some(config.strict_health),
+            tcp_nodelay: Some(config.tcp_nodelay),
+            tcp_keepalive: config.tcp_keepalive.map(seconds),
+            source_addr: config.source_addr.map(|addr| addr.to_string()),
+            adaptive_latency_weight: Some(percent(config.adaptive_latency_weight)),
+        }
+    }
+
+    // Translate the file into `key=value` arguments, so it is validated exactly like the command line
+    fn to_arguments(&self) -> Vec<String> {
+        let mut args = Vec::new();
+
+        let mut push = |key: &str, value: Option<String>| {
+            if let Some(value) = value {
+                args.push(format!("{}={}", key, value));
+            }
+        };
+        push("mode", self.mode.clone());
+        push("group_mode", self.group_mode.clone());
+        push("rr_scope", self.rr_scope.clone());
+        push("proto", self.proto.clone());
+        push("ring_domain", self.ring_domain.clone());
+        push("ring_srv", self.ring_srv.clone());
+        push("ring_interval", self.ring_interval.map(|v| v.to_string()));
+        push("ring_keep_last", self.ring_keep_last.map(|v| v.to_string()));
+        push("timer_jitter", self.timer_jitter.map(|v| v.to_string()));
+        push("rdns", self.rdns.map(|v| v.to_string()));
+        push("rdns_cache_ttl", self.rdns_cache_ttl.map(|v| v.to_string()));
+        push("ip_family", self.ip_family.clone());
+        push("health_interval", self.health_interval.map(|v| v.to_string()));
+        push("health_timeout", self.health_timeout.map(|v| v.to_string()));
+        push("health_concurrency", self.health_concurrency.map(|v| v.to_string()));
+        push("health_http_path", self.health_http_path.clone());
+        push("health_grpc", self.health_grpc.clone());
+        push("udp_probe", self.udp_probe.clone());
+        push("udp_expect", self.udp_expect.clone());
+        push("rise", self.rise.map(|v| v.to_string()));
+        push("fall", self.fall.map(|v| v.to_string()));
+        push("passive_failures", self.passive_failures.map(|v| v.to_string()));
+        push("passive_window", self.passive_window.map(|v| v.to_string()));
+        push("outlier_5xx_ratio", self.outlier_5xx_ratio.map(|v| v.to_string()));
+        push("outlier_window", self.outlier_window.map(|v| v.to_string()));
+        push("outlier_cooldown", self.outlier_cooldown.map(|v| v.to_string()));
+        push("circuit_failures", self.circuit_failures.map(|v| v.to_string()));
+        push("circuit_cooldown", self.circuit_cooldown.map(|v| v.to_string()));
+        push("idle_timeout", self.idle_timeout.map(|v| v.to_string()));
+        push("proxy_protocol", self.proxy_protocol.clone());
+        push("sni_routing", self.sni_routing.map(|v| v.to_string()));
+        push("retries", self.retries.map(|v| v.to_string()));
+        push("connect_timeout", self.connect_timeout.map(|v| v.to_string()));
+        push("no_backend_wait", self.no_backend_wait.map(|v| v.to_string()));
+        push("tcp_pool_size", self.tcp_pool_size.map(|v| v.to_string()));
+        push("tcp_pool_idle", self.tcp_pool_idle.map(|v| v.to_string()));
+        push("tls_cert", self.tls_cert.clone());
+        push("tls_key", self.tls_key.clone());
+        push("udp_session_timeout", self.udp_session_timeout.map(|v| v.to_string()));
+        push("udp_response_timeout", self.udp_response_timeout.map(|v| v.to_string()));
+        push("udp_response_idle_ms", self.udp_response_idle_ms.map(|v| v.to_string()));
+        push("udp_batch", self.udp_batch.map(|v| v.to_string()));
+        push("udp_max_datagram", self.udp_max_datagram.map(|v| v.to_string()));
+        push("udp_max_sessions", self.udp_max_sessions.map(|v| v.to_string()));
+        push("drain_timeout", self.drain_timeout.map(|v| v.to_string()));
+        push("drain_state", self.drain_state.clone());
+        push("uds_path", self.uds_path.clone());
+        push("uds_mode", self.uds_mode.clone());
+        push("uds_owner", self.uds_owner.clone());
+        push("uds_group", self.uds_group.clone());
+        push("uds_token", self.uds_token.clone());
+        push("uds_token_reads", self.uds_token_reads.map(|v| v.to_string()));
+        push("metrics_addr", self.metrics_addr.clone());
+        push("http_health_addr", self.http_health_addr.clone());
+        push("log_format", self.log_format.clone());
+        push("log_level", self.log_level.clone());
+        push("access_log", self.access_log.map(|v| v.to_string()));
+        push("log_file", self.log_file.clone());
+        push("log_stdout", self.log_stdout.map(|v| v.to_string()));
+        push("log_max_size", self.log_max_size.map(|v| v.to_string()));
+        push("max_conns", self.max_conns.map(|v| v.to_string()));
+        push("max_global_conns", self.max_global_conns.map(|v| v.to_string()));
+        push("conn_rate", self.conn_rate.map(|v| v.to_string()));
+        push("slow_start", self.slow_start.map(|v| v.to_string()));
+        push("workers", self.workers.map(|v| v.to_string()));
+        push("bind_retry", self.bind_retry.map(|v| v.to_string()));
+        push("reuse_addr", self.reuse_addr.map(|v| v.to_string()));
+        push("wait_for_health", self.wait_for_health.map(|v| v.to_string()));
+        push("strict_health", self.strict_health.map(|v| v.to_string()));
+        push("tcp_nodelay", self.tcp_nodelay.map(|v| v.to_string()));
+        push("tcp_keepalive", self.tcp_keepalive.map(|v| v.to_string()));
+        push("source_addr", self.source_addr.clone());
+        push("adaptive_latency_weight", self.adaptive_latency_weight.map(|v| v.to_string()));
+        push("backends", self.backends.as_ref().map(|backends| {
+            backends
+                .iter()
+                .map(|b| {
+                    let mut entry = match &b.group {
+                        Some(group) => format!("{}@{}", group, b.addr),
+                        None => b.addr.clone(),
+                    };
+                    if let Some(protocol) = &b.protocol {
+                        entry.push_str(&format!("/{}", protocol));
+                    }
+                    if let Some(weight) = b.weight {
+                        entry.push_str(&format!("*{}", weight));
+                    }
+                    if let Some(max_conns) = b.max_conns {
+                        entry.push_str(&format!("#{}", max_conns));
+                    }
+                    if let Some(tier) = b.tier {
+                        entry.push_str(&format!("^{}", tier));
+                    }
+                    entry
+                })
+                .collect::<Vec<String>>()
+                .join(",")
+        }));
+
+        args
+    }
+}
+
+// Whether an argument configures backends, used to let command-line backends replace the file's
+pub fn is_backend_argument(arg: &str) -> bool {
+    arg.starts_with("backends=") || !arg.contains('=')
+}
+
+// Load a TOML configuration file and merge it with the remaining command-line arguments.
+// The result is an argument list for `parse_arguments`, command-line values override the file.
+pub fn load_config_file(path: &str, cli_args: &[String]) -> Result<Vec<String>, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read config file {}: {}", path, e))?;
+    let file: FileConfig = toml::from_str(&content)
+        .map_err(|e| format!("Invalid config file {}: {}", path, e))?;
+
+    // A leading positional argument is the bind address, just like without a config file
+    let (cli_bind, cli_rest) = match cli_args.first() {
+        Some(first) if !first.contains('=') => (Some(first.clone()), &cli_args[1..]),
+        _ => (None, cli_args),
+    };
+
+    let bind = cli_bind
+        .or(file.bind.clone())
+        .ok_or_else(|| format!("No bind address given in {} or on the command line", path))?;
+
+    let cli_has_backends = cli_rest.iter().any(|arg| is_backend_argument(arg));
+    let mut args = vec![bind];
+    args.extend(
+        file.to_arguments()
+            .into_iter()
+            .filter(|arg| !(cli_has_backends && is_backend_argument(arg))),
+    );
+    args.extend(cli_rest.iter().cloned());
+
+    Ok(args)
+}
+
+// Turn the process arguments (without the program name) into the argument list for `parse_arguments`,
+// merging the file given with `--config <path>` if there is one. Used at startup and on every reload.
+pub fn resolve_arguments(args: &[String]) -> Result<Vec<String>, String> {
+    match args.iter().position(|arg| arg == "--config") {
+        Some(pos) => {
+            let path = args.get(pos + 1).ok_or("--config requires a path to a TOML file")?;
+            let rest: Vec<String> = args[..pos].iter().chain(args[pos + 2..].iter()).cloned().collect();
+            load_config_file(path, &rest)
+        }
+        None => Ok(args.to_vec()),
+    }
+}
@@ -1,64 +1,408 @@
-use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Instant;
 use trust_dns_resolver::TokioAsyncResolver;
 use trust_dns_resolver::config::*;
+use trust_dns_resolver::error::{ResolveError, ResolveErrorKind};
+use trust_dns_resolver::system_conf::read_system_conf;
 use std::collections::HashMap;
-use crate::modules::utils::log;
-use crate::modules::load_balancer::Protocol;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::{sleep, Duration};
+use crate::modules::logging::{log, log_warn};
+use crate::modules::load_balancer::{LoadBalancer, Protocol};
+use crate::modules::utils::jittered;
 
-pub async fn resolve_ring_domain(ring_domain: &str, protocol: Protocol) -> Vec<(SocketAddr, Option<Protocol>)> {
+// Bounds for TTL-driven re-resolution, the upper bound is the configured ring interval
+const MIN_RING_REFRESH: Duration = Duration::from_secs(5);
+
+// Address families to keep from a ring-domain resolution
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IpFamily {
+    V4,
+    V6,
+    Any,
+}
+
+impl std::str::FromStr for IpFamily {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<IpFamily, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "v4" => Ok(IpFamily::V4),
+            "v6" => Ok(IpFamily::V6),
+            "any" => Ok(IpFamily::Any),
+            _ => Err(()),
+        }
+    }
+}
+
+impl std::fmt::Display for IpFamily {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            IpFamily::V4 => "v4",
+            IpFamily::V6 => "v6",
+            IpFamily::Any => "any",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl IpFamily {
+    fn lookup_strategy(self) -> LookupIpStrategy {
+        match self {
+            IpFamily::V4 => LookupIpStrategy::Ipv4Only,
+            IpFamily::V6 => LookupIpStrategy::Ipv6Only,
+            IpFamily::Any => LookupIpStrategy::Ipv4AndIpv6,
+        }
+    }
+
+    fn allows(self, ip: &IpAddr) -> bool {
+        match self {
+            IpFamily::V4 => ip.is_ipv4(),
+            IpFamily::V6 => ip.is_ipv6(),
+            IpFamily::Any => true,
+        }
+    }
+}
+
+// Settings shared by the initial and periodic resolution of every ring domain
+#[derive(Debug, Clone)]
+pub struct RingConfig {
+    pub protocols: Vec<Protocol>,  // One backend entry is created per protocol for each address
+    pub ip_family: IpFamily,
+    pub max_interval: Duration,    // Upper bound between two re-resolutions
+    pub jitter: f64,  // Random share by which each delay is lengthened or shortened
+    pub rdns: Option<Arc<RdnsCache>>,  // Reverse lookups of resolved addresses for the log, shared by all ring domains
+    pub keep_last: bool,  // Keep the previous members when a re-resolution finds no backends, instead of removing the group
+}
+
+// Reverse-DNS names of resolved addresses. The resolver is built once and every answer, including
+// a missing PTR record, is kept for `ttl` so re-resolving a ring does not repeat the lookups.
+pub struct RdnsCache {
+    resolver: TokioAsyncResolver,
+    ttl: Duration,
+    entries: Mutex<HashMap<IpAddr, (Option<String>, Instant)>>,
+}
+
+impl std::fmt::Debug for RdnsCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RdnsCache").field("ttl", &self.ttl).finish_non_exhaustive()
+    }
+}
+
+impl RdnsCache {
+    pub fn new(ttl: Duration) -> Self {
+        RdnsCache {
+            resolver: TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default()),
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Name of the first PTR record of `ip`, served from memory while the cached answer is fresh
+    pub async fn lookup(&self, ip: IpAddr) -> Option<String> {
+        if let Some((name, at)) = self.entries.lock().await.get(&ip) {
+            if at.elapsed() < self.ttl {
+                return name.clone();
+            }
+        }
+
+        let name = match self.resolver.reverse_lookup(ip).await {
+            Ok(names) => names.iter().next().map(|name| name.to_string()),
+            Err(_) => None, // Return None if reverse lookup fails
+        };
+
+        let mut entries = self.entries.lock().await;
+        entries.retain(|_, (_, at)| at.elapsed() < self.ttl);
+        if !self.ttl.is_zero() {
+            entries.insert(ip, (name.clone(), Instant::now()));
+        }
+        name
+    }
+}
+
+// Backends of a ring source and the time until its records expire (if known)
+pub type ResolvedBackends = (Vec<(SocketAddr, Option<Protocol>, u32)>, Option<Duration>);
+
+// A static backend given by host name instead of address, e.g. `db.internal:5432/tcp*2`
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostBackend {
+    pub host: String,
+    pub port: u16,
+    pub protocol: Option<Protocol>,  // None follows proto=
+    pub weight: u32,
+}
+
+// A name that is resolved into a backend group, either by its address records or its SRV records,
+// or the host name backends of a static group (by label)
+#[derive(Debug, Clone)]
+pub enum RingSource {
+    Domain(String),
+    Srv(String),
+    Hosts(String, Vec<HostBackend>),
+}
+
+impl RingSource {
+    // Group label of the resolved backends
+    pub fn name(&self) -> &str {
+        match self {
+            RingSource::Domain(name) | RingSource::Srv(name) | RingSource::Hosts(name, _) => name,
+        }
+    }
+
+    // What is resolved, for log messages
+    pub fn describe(&self) -> String {
+        match self {
+            RingSource::Domain(name) => format!("ring domain {}", name),
+            RingSource::Srv(name) => format!("SRV name {}", name),
+            RingSource::Hosts(label, hosts) => match hosts.as_slice() {
+                [host] if host.host == *label => format!("host name {}", label),
+                _ => {
+                    let hosts: Vec<&str> = hosts.iter().map(|host| host.host.as_str()).collect();
+                    format!("host names {} of group {}", hosts.join(", "), label)
+                }
+            },
+        }
+    }
+
+    pub async fn resolve(&self, ring: &RingConfig) -> Result<ResolvedBackends, ResolveError> {
+        let (backends, ttl) = match self {
+            RingSource::Domain(ring_domain) => resolve_ring_backends(ring_domain, ring).await,
+            RingSource::Srv(srv_name) => resolve_srv_backends(srv_name, ring).await,
+            RingSource::Hosts(_, hosts) => resolve_host_backends(hosts, ring).await,
+        }?;
+        Ok((merge_weights(backends), ttl))
+    }
+}
+
+// Several records can lead to the same address, e.g. SRV records sharing a target or targets sharing
+// an IP. Such an address is a single backend with the capacity of all its records, their weights add up.
+fn merge_weights(backends: Vec<(SocketAddr, Option<Protocol>, u32)>) -> Vec<(SocketAddr, Option<Protocol>, u32)> {
+    let mut merged: Vec<(SocketAddr, Option<Protocol>, u32)> = Vec::new();
+    for (addr, protocol, weight) in backends {
+        match merged.iter_mut().find(|(known, known_protocol, _)| *known == addr && *known_protocol == protocol) {
+            Some((_, _, total)) => *total = total.saturating_add(weight),
+            None => merged.push((addr, protocol, weight)),
+        }
+    }
+    merged
+}
+
+// Resolver built from the system configuration, restricted to the requested address families
+fn build_resolver(ip_family: IpFamily) -> TokioAsyncResolver {
+    let (resolver_config, mut resolver_opts) = read_system_conf()
+        .unwrap_or_else(|_| (ResolverConfig::default(), ResolverOpts::default()));
+    resolver_opts.ip_strategy = ip_family.lookup_strategy();
+    TokioAsyncResolver::tokio(resolver_config, resolver_opts)
+}
+
+// An answer without records is a valid (empty) result rather than a failed query
+fn empty_answer(error: &ResolveError) -> Option<Option<Duration>> {
+    match error.kind() {
+        ResolveErrorKind::NoRecordsFound { negative_ttl, .. } => Some(negative_ttl.map(|ttl| Duration::from_secs(ttl.into()))),
+        _ => None,
+    }
+}
+
+// Resolve a ring domain, returning the addresses and the smallest TTL of the answer (if known).
+// A name without address records resolves to no addresses, an error means the query itself failed.
+pub async fn resolve_ring_domain(ring_domain: &str, protocol: Protocol, ip_family: IpFamily, rdns: Option<&RdnsCache>) -> Result<(Vec<(SocketAddr, Option<Protocol>)>, Option<Duration>), ResolveError> {
     let mut result = Vec::new();
     let mut ip_map: HashMap<String, Vec<(SocketAddr, String)>> = HashMap::new();
 
-    // Split the ring_domain into hostname and port if port is specified
-    let (hostname, port) = match ring_domain.split_once(':') {
+    // Split the ring_domain into hostname and port if port is specified, IPv6 literals are bracketed
+    let (hostname, port) = match ring_domain.rsplit_once(':') {
         Some((host, port)) => {
             match port.parse::<u16>() {
-                Ok(p) => (host, p),
-                Err(_) => {
-                    log(format!("Invalid port provided for {}: please specify a valid port", ring_domain));
-                    return result; // Return early if the port is invalid
-                }
+                Ok(p) => (host.trim_start_matches('[').trim_end_matches(']'), p),
+                Err(_) => return Err(format!("invalid port in {}", ring_domain).into()),
             }
         },
-        None => {
-            log(format!("No port specified for {}: a port is required!", ring_domain));
-            return result; // Return early if no port is specified
-        }
+        None => return Err(format!("no port specified for {}", ring_domain).into()),
     };
 
-    // Resolve hostname to a list of SocketAddr using to_socket_addrs()
-    match (hostname, port).to_socket_addrs() {
-        Ok(iter) => {
-            for socket_addr in iter {
-                let rdns_name = resolve_rdns_name(socket_addr.ip()).await.unwrap_or_else(|| "<unknown>".to_string());
+    // Resolve hostname with the system resolver configuration, which also exposes the record TTL
+    let resolver = build_resolver(ip_family);
+
+    let ttl;
+    match resolver.lookup_ip(hostname).await {
+        Ok(lookup) => {
+            ttl = Some(lookup.valid_until().saturating_duration_since(Instant::now()));
+            for ip in lookup.iter().filter(|ip| ip_family.allows(ip)) {
+                let socket_addr = SocketAddr::new(ip, port);
+                let rdns_name = match rdns {
+                    Some(rdns) => rdns.lookup(socket_addr.ip()).await.unwrap_or_else(|| "<unknown>".to_string()),
+                    None => String::new(),
+                };
 
                 // Use the provided protocol, either UDP or TCP
                 result.push((socket_addr, Some(protocol)));
-                ip_map.entry(rdns_name.clone()).or_insert_with(Vec::new).push((socket_addr, rdns_name));
+                ip_map.entry(rdns_name.clone()).or_default().push((socket_addr, rdns_name));
             }
             for (rdns_name, addresses) in ip_map {
                 let ip_list: Vec<String> = addresses.iter().map(|(addr, _)| addr.to_string()).collect();
+                if rdns.is_some() {
+                    log(format!("Resolved {} to {} ({})", hostname, ip_list.join(", "), rdns_name));
+                } else {
+                    log(format!("Resolved {} to {}", hostname, ip_list.join(", ")));
+                }
+            }
+        }
+        Err(e) => match empty_answer(&e) {
+            Some(negative_ttl) => ttl = negative_ttl,
+            None => return Err(e),
+        },
+    }
+
+    Ok((result, ttl))
+}
+
+// Resolve a ring domain into one backend entry per resolved address and listening protocol
+pub async fn resolve_ring_backends(ring_domain: &str, ring: &RingConfig) -> Result<ResolvedBackends, ResolveError> {
+    let mut backends = Vec::new();
+    let (resolved, ttl) = resolve_ring_domain(ring_domain, ring.protocols[0], ring.ip_family, ring.rdns.as_deref()).await?;
+    for (addr, _) in resolved {
+        for &protocol in &ring.protocols {
+            backends.push((addr, Some(protocol), 1));
+        }
+    }
+    Ok((backends, ttl))
+}
+
+// Resolve the host names of a static group, each entry keeping its own protocol and weight. The group
+// is replaced as a whole, so a single failed lookup fails the resolution and keeps all current members.
+pub async fn resolve_host_backends(hosts: &[HostBackend], ring: &RingConfig) -> Result<ResolvedBackends, ResolveError> {
+    let mut backends = Vec::new();
+    let mut ttl: Option<Duration> = None;
+    for host in hosts {
+        let protocols = host.protocol.map_or_else(|| ring.protocols.clone(), |protocol| vec![protocol]);
+        let (resolved, host_ttl) = resolve_ring_domain(&format!("{}:{}", host.host, host.port), protocols[0], ring.ip_family, ring.rdns.as_deref()).await?;
+        for (addr, _) in resolved {
+            for &protocol in &protocols {
+                backends.push((addr, Some(protocol), host.weight));
+            }
+        }
+        ttl = match (ttl, host_ttl) {
+            (Some(ttl), Some(host_ttl)) => Some(ttl.min(host_ttl)),
+            (ttl, host_ttl) => ttl.or(host_ttl),
+        };
+    }
+    Ok((backends, ttl))
+}
+
+// Resolve SRV records into backends using the record port and weight. Only the targets of the
+// lowest priority present in the answer are used, higher priorities are fallbacks that only
+// come into play once the lower ones are withdrawn from DNS. Fails if the SRV query fails or
+// none of the targets could be resolved.
+pub async fn resolve_srv_backends(srv_name: &str, ring: &RingConfig) -> Result<ResolvedBackends, ResolveError> {
+    let mut backends = Vec::new();
+    let resolver = build_resolver(ring.ip_family);
+
+    let lookup = match resolver.srv_lookup(srv_name).await {
+        Ok(lookup) => lookup,
+        Err(e) => {
+            return match empty_answer(&e) {
+                Some(negative_ttl) => Ok((backends, negative_ttl)),
+                None => Err(e),
+            };
+        }
+    };
+    let mut ttl = lookup.as_lookup().valid_until().saturating_duration_since(Instant::now());
+
+    // A target of "." means the service is explicitly not available at this name
+    let records: Vec<_> = lookup.iter().filter(|srv| !srv.target().is_root()).collect();
+    let Some(priority) = records.iter().map(|srv| srv.priority()).min() else {
+        log_warn(format!("No SRV targets found for {}", srv_name));
+        return Ok((backends, Some(ttl)));
+    };
+
+    let mut target_error = None;  // Only reported if no target resolved at all
+    for srv in records.iter().filter(|srv| srv.priority() == priority) {
+        let target = srv.target().to_utf8();
+        // A weight of 0 still has to be selectable when it is the only target left
+        let weight = u32::from(srv.weight()).max(1);
+        match resolver.lookup_ip(srv.target().clone()).await {
+            Ok(ips) => {
+                ttl = ttl.min(ips.valid_until().saturating_duration_since(Instant::now()));
+                let addrs: Vec<SocketAddr> = ips
+                    .iter()
+                    .filter(|ip| ring.ip_family.allows(ip))
+                    .map(|ip| SocketAddr::new(ip, srv.port()))
+                    .collect();
+                let addr_list: Vec<String> = addrs.iter().map(|addr| addr.to_string()).collect();
                 log(format!(
-                    "Resolved {} to {} ({})",
-                    hostname,
-                    ip_list.join(", "),
-                    rdns_name
+                    "Resolved SRV target {} (priority {}, weight {}) to {}",
+                    target,
+                    priority,
+                    srv.weight(),
+                    addr_list.join(", ")
                 ));
+                for addr in addrs {
+                    for &protocol in &ring.protocols {
+                        backends.push((addr, Some(protocol), weight));
+                    }
+                }
             }
+            Err(e) => match empty_answer(&e) {
+                Some(_) => log_warn(format!("SRV target {} of {} has no address records", target, srv_name)),
+                None => {
+                    log_warn(format!("Failed to resolve SRV target {} of {}: {:?}", target, srv_name, e));
+                    target_error = Some(e);
+                }
+            },
         }
-        Err(e) => eprintln!("Failed to resolve ring address {}: {:?}", ring_domain, e),
     }
 
-    result
+    match target_error {
+        Some(e) if backends.is_empty() => Err(e),
+        _ => Ok((backends, Some(ttl))),
+    }
+}
+
+// Time until the next re-resolution: the record TTL, clamped between a small floor and the
+// configured ring interval. Without a TTL the ring interval is used.
+pub fn next_ring_refresh(ttl: Option<Duration>, max_interval: Duration) -> Duration {
+    match ttl {
+        Some(ttl) => ttl.clamp(MIN_RING_REFRESH.min(max_interval), max_interval),
+        None => max_interval,
+    }
 }
 
-pub async fn resolve_rdns_name(ip: IpAddr) -> Option<String> {
-    // Create an async DNS resolver
-    let resolver = TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default());
+// Periodically re-resolve a ring domain and apply membership changes to its group
+pub async fn refresh_ring_domain(lb: Arc<LoadBalancer>, source: RingSource, ring: RingConfig, first_delay: Duration) {
+    let mut delay = first_delay;
+    let mut stale = false;  // The group still holds members of an earlier resolution
+    loop {
+        sleep(jittered(delay, ring.jitter)).await;
+        let (resolved, ttl) = match source.resolve(&ring).await {
+            Ok(result) => result,
+            Err(e) => {
+                // A failed query says nothing about the members, they stay until DNS answers again
+                log_warn(format!("Failed to resolve {}, keeping its current backends: {}", source.describe(), e));
+                delay = next_ring_refresh(None, ring.max_interval);
+                stale = true;
+                continue;
+            }
+        };
+        delay = next_ring_refresh(ttl, ring.max_interval);
 
-    // Perform reverse DNS lookup
-    match resolver.reverse_lookup(ip).await {
-        Ok(names) => names.iter().next().map(|name| name.to_string()),
-        Err(_) => None, // Return None if reverse lookup fails
+        // A DNS outage should not take down backends that are most likely still serving
+        if resolved.is_empty() && ring.keep_last {
+            let kept = lb.backends.lock().await.get(source.name()).map_or(0, Vec::len);
+            if kept > 0 {
+                log_warn(format!(
+                    "Resolving ring domain {} found no backends, keeping the {} previously resolved ones until it succeeds again",
+                    source.name(),
+                    kept
+                ));
+                stale = true;
+                continue;
+            }
+        }
+        if stale {
+            log(format!("Ring domain {} resolves again, replacing the retained backends", source.name()));
+            stale = false;
+        }
+        lb.update_dynamic_backends(source.name(), resolved).await;
     }
 }
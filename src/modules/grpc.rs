@@ -0,0 +1,149 @@
+use std::net::{IpAddr, SocketAddr};
+use bytes::Bytes;
+use http::{HeaderMap, Method, Request};
+use tokio::time::{timeout, Duration};
+use crate::modules::health::ProbeError;
+use crate::modules::sockets::connect_tcp;
+
+const MAX_RESPONSE_SIZE: usize = 4096; // A HealthCheckResponse is a few bytes, anything larger is not one
+const SERVING: u64 = 1; // grpc.health.v1.HealthCheckResponse.ServingStatus.SERVING
+
+impl From<h2::Error> for ProbeError {
+    fn from(error: h2::Error) -> Self {
+        match error.into_io() {
+            Some(e) => e.into(),
+            None => ProbeError::BadResponse,
+        }
+    }
+}
+
+// A gRPC backend is healthy if grpc.health.v1.Health/Check for `service` answers SERVING.
+// The check speaks HTTP/2 without TLS (h2c with prior knowledge), as gRPC servers behind a balancer usually do.
+pub async fn probe_grpc(addr: SocketAddr, source: Option<IpAddr>, service: &str, probe_timeout: Duration) -> Result<(), ProbeError> {
+    let probe = async {
+        let stream = connect_tcp(addr, source).await?;
+        let (client, connection) = h2::client::handshake(stream).await?;
+        // The connection ends once the client is dropped at the end of the probe
+        tokio::spawn(async move {
+            let _ = connection.await;
+        });
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(format!("http://{}/grpc.health.v1.Health/Check", addr))
+            .header("content-type", "application/grpc")
+            .header("te", "trailers")
+            .header("user-agent", format!("SideLB/{}", env!("CARGO_PKG_VERSION")))
+            .body(())
+            .map_err(|_| ProbeError::BadResponse)?;
+        let mut client = client.ready().await?;
+        let (response, mut send) = client.send_request(request, false)?;
+        send.send_data(check_request(service), true)?;
+
+        let (parts, mut body) = response.await?.into_parts();
+        if parts.status != 200 {
+            return Err(ProbeError::HttpStatus(parts.status.as_u16()));
+        }
+        // An error without a message comes as headers only
+        if let Some(status) = grpc_status(&parts.headers).filter(|status| *status != 0) {
+            return Err(ProbeError::GrpcStatus(status));
+        }
+
+        let mut message = Vec::new();
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk?;
+            let _ = body.flow_control().release_capacity(chunk.len());
+            message.extend_from_slice(&chunk);
+            if message.len() > MAX_RESPONSE_SIZE {
+                return Err(ProbeError::BadResponse);
+            }
+        }
+        let trailers = body.trailers().await?.unwrap_or_default();
+        match grpc_status(&trailers).or_else(|| grpc_status(&parts.headers)) {
+            Some(0) => {}
+            Some(status) => return Err(ProbeError::GrpcStatus(status)),
+            None => return Err(ProbeError::BadResponse),
+        }
+
+        match serving_status(&message) {
+            Some(SERVING) => Ok(()),
+            Some(status) => Err(ProbeError::NotServing(status)),
+            None => Err(ProbeError::BadResponse),
+        }
+    };
+
+    match timeout(probe_timeout, probe).await {
+        Ok(result) => result,
+        Err(_) => Err(ProbeError::Timeout),
+    }
+}
+
+// Length-prefixed gRPC message holding a HealthCheckRequest, an empty service asks for the whole server
+fn check_request(service: &str) -> Bytes {
+    let mut message = Vec::new();
+    if !service.is_empty() {
+        message.push(0x0a); // Field 1 (service), length-delimited
+        put_varint(&mut message, service.len() as u64);
+        message.extend_from_slice(service.as_bytes());
+    }
+
+    let mut frame = vec![0u8]; // Not compressed
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&message);
+    Bytes::from(frame)
+}
+
+fn grpc_status(headers: &HeaderMap) -> Option<u32> {
+    headers.get("grpc-status")?.to_str().ok()?.parse().ok()
+}
+
+// Status field of the HealthCheckResponse in a length-prefixed gRPC message, 0 (UNKNOWN) if it is left out
+fn serving_status(frame: &[u8]) -> Option<u64> {
+    let (&compressed, rest) = frame.split_first()?;
+    let length = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+    if compressed != 0 {
+        return None;
+    }
+    let mut message = rest.get(4..4 + length)?;
+
+    let mut status = 0;
+    while !message.is_empty() {
+        let key = take_varint(&mut message)?;
+        match (key >> 3, key & 0x7) {
+            (1, 0) => status = take_varint(&mut message)?,
+            // Skip fields a newer server may add
+            (_, 0) => {
+                take_varint(&mut message)?;
+            }
+            (_, 1) => message = message.get(8..)?,
+            (_, 2) => {
+                let length = take_varint(&mut message)? as usize;
+                message = message.get(length..)?;
+            }
+            (_, 5) => message = message.get(4..)?,
+            _ => return None,
+        }
+    }
+    Some(status)
+}
+
+fn put_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn take_varint(buffer: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = buffer.split_first()?;
+        *buffer = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
@@ -1,98 +1,610 @@
 use tokio::net::{TcpStream, UdpSocket};
-use tokio::io::split;
+use tokio::io::{split, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::watch;
+use tokio::task::AbortHandle;
+use tokio::time::{interval, sleep, timeout, Duration, Instant};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU16, AtomicU64, Ordering};
 use std::sync::Arc;
-use crate::modules::load_balancer::{LoadBalancer, Protocol};
-use crate::modules::utils::log;
+use crate::modules::health::parse_status_code;
+use crate::modules::load_balancer::{Backend, ConnectionGuard, LoadBalancer, Protocol};
+use crate::modules::metrics::Metrics;
+use crate::modules::pool::ConnectionPool;
+use crate::modules::proxy_protocol::{proxy_header, ProxyProtocol};
+use crate::modules::sni::read_client_hello;
+use crate::modules::sockets::{configure_tcp_stream, connect_tcp, local_addr_for, UdpBatch};
+#[cfg(feature = "tls")]
+use crate::modules::tls::TLS_HANDSHAKE_TIMEOUT;
+use crate::modules::logging::{log_debug, log_error, log_event, log_warn, LogLevel};
+use serde_json::json;
+#[cfg(feature = "tls")]
+use tokio_rustls::TlsAcceptor;
 
-pub async fn handle_tcp(inbound: TcpStream, lb: Arc<LoadBalancer>) {
-    let _client_addr = inbound.peer_addr().expect("Failed to get client address");
-    let backend = {
-        lb.next_backend().await
+// Time of the last byte forwarded in either direction of a TCP connection or UDP session
+struct Activity {
+    start: Instant,
+    last_millis: AtomicU64,  // Milliseconds since `start`
+}
+
+impl Activity {
+    fn new() -> Self {
+        Activity { start: Instant::now(), last_millis: AtomicU64::new(0) }
+    }
+
+    fn touch(&self) {
+        self.last_millis.store(self.start.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    fn idle(&self) -> Duration {
+        self.start.elapsed().saturating_sub(Duration::from_millis(self.last_millis.load(Ordering::Relaxed)))
+    }
+}
+
+// Side of a copy that failed
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CopyFailure {
+    Read,
+    Write,
+}
+
+// Copy one direction like `tokio::io::copy`, recording activity and the forwarded byte count as it goes.
+// With `http_status`, the status code of an HTTP response at the start of the stream is stored there.
+async fn copy_tracked<R, W>(
+    mut reader: R,
+    mut writer: W,
+    activity: Arc<Activity>,
+    bytes: Arc<AtomicU64>,
+    http_status: Option<Arc<AtomicU16>>,
+    direction: &'static str,
+) -> Option<CopyFailure>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0u8; 8192];
+    let mut http_status = http_status;
+    loop {
+        let len = match reader.read(&mut buf).await {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(e) => {
+                log_warn(format!("Error forwarding from {}: {:?}", direction, e));
+                // Like an EOF, otherwise a peer waiting for more input would keep the connection open
+                let _ = writer.shutdown().await;
+                return Some(CopyFailure::Read);
+            }
+        };
+        // Only the first chunk is looked at, the status line of a response fits into it
+        if let Some(http_status) = http_status.take() {
+            let status_line = buf[..len].split(|&b| b == b'\n').next().unwrap_or_default();
+            if let Some(status) = parse_status_code(&String::from_utf8_lossy(status_line)) {
+                http_status.store(status, Ordering::Relaxed);
+            }
+        }
+        if let Err(e) = writer.write_all(&buf[..len]).await {
+            log_warn(format!("Error forwarding from {}: {:?}", direction, e));
+            return Some(CopyFailure::Write);
+        }
+        bytes.fetch_add(len as u64, Ordering::Relaxed);
+        activity.touch();
+    }
+    // Pass the end of stream on, the other direction may still be sending
+    let _ = writer.shutdown().await;
+    None
+}
+
+// Resolves once no bytes flowed for `idle_timeout`, never resolves without a timeout
+async fn wait_for_idle(activity: &Activity, idle_timeout: Option<Duration>) {
+    let Some(idle_timeout) = idle_timeout else {
+        return std::future::pending().await;
     };
+    loop {
+        let idle = activity.idle();
+        if idle >= idle_timeout {
+            return;
+        }
+        sleep(idle_timeout - idle).await;
+    }
+}
 
-    if let Some(backend) = backend {
-        log(format!("Forwarding TCP connection to backend: {} (Protocol: {:?})", backend.addr, backend.protocol));
-        lb.increment_connection(backend).await; // Increment connection count
-
-        match backend.protocol {
-            Protocol::TCP => {
-                match TcpStream::connect(backend.addr).await {
-                    Ok(outbound) => {
-                        let (mut ri, mut wi) = split(inbound);
-                        let (mut ro, mut wo) = split(outbound);
-
-                        let client_to_server = tokio::spawn(async move {
-                            if let Err(e) = tokio::io::copy(&mut ri, &mut wo).await {
-                                eprintln!("Error forwarding from client to server: {:?}", e);
-                            }
-                        });
-
-                        let server_to_client = tokio::spawn(async move {
-                            if let Err(e) = tokio::io::copy(&mut ro, &mut wi).await {
-                                eprintln!("Error forwarding from server to client: {:?}", e);
-                            }
-                        });
-
-                        if let Err(e) = tokio::try_join!(client_to_server, server_to_client) {
-                            eprintln!("Error joining copy tasks: {:?}", e);
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("Failed to connect to backend: {}. Error: {:?}", backend.addr, e);
-                    }
-                }
+// Per-connection behavior of the TCP listener
+#[derive(Clone, Default)]
+pub struct TcpConfig {
+    pub idle_timeout: Option<Duration>,  // Close connections without traffic for this long
+    pub proxy_protocol: Option<ProxyProtocol>,  // Announce the client address to the backend
+    pub sni_routing: bool,  // Route TLS connections to the group named by their SNI host name
+    pub retries: u32,  // Other backends to try when connecting to the selected one fails
+    pub connect_timeout: Duration,  // Upper bound for establishing a backend connection
+    pub no_backend_wait: Option<Duration>,  // Hold a client this long for a backend to become available before closing it
+    pub pool: Option<Arc<ConnectionPool>>,  // Connections opened ahead of time, handed to new clients
+    pub access_log: bool,  // Log one summary line per finished connection
+    pub nodelay: bool,  // Disable Nagle's algorithm on client and backend sockets
+    pub keepalive: Option<Duration>,  // Probe idle client and backend sockets for dead peers after this long
+    #[cfg(feature = "tls")]
+    pub tls_acceptor: Option<TlsAcceptor>,  // Terminate TLS and forward plaintext to the backends
+}
+
+pub async fn handle_tcp(mut inbound: TcpStream, lb: Arc<LoadBalancer>, tcp: Arc<TcpConfig>) {
+    let client_addr = inbound.peer_addr().expect("Failed to get client address");
+    if let Err(e) = configure_tcp_stream(&inbound, tcp.nodelay, tcp.keepalive) {
+        log_warn(format!("Failed to set socket options for client {}: {:?}", client_addr, e));
+    }
+
+    // Clients opening connections faster than conn_rate are turned away before anything else happens
+    if let Some(conn_rate) = &lb.conn_rate {
+        if !conn_rate.allow(client_addr.ip()).await {
+            return;
+        }
+    }
+
+    // Fail fast at the global limit instead of queueing, the permit is held until the connection closes
+    let _permit = match &lb.global_conns {
+        Some(global_conns) => match global_conns.clone().try_acquire_owned() {
+            Ok(permit) => Some(permit),
+            Err(_) => {
+                log_warn(format!("Global connection limit reached, rejecting TCP connection from {}", client_addr));
+                return;
             }
-            Protocol::UDP => {
-                eprintln!("Received a TCP connection, but backend expects UDP for backend: {}", backend.addr);
+        },
+        None => None,
+    };
+
+    // With TLS termination the SNI comes from the completed handshake
+    #[cfg(feature = "tls")]
+    if let Some(acceptor) = &tcp.tls_acceptor {
+        match timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(inbound)).await {
+            Ok(Ok(stream)) => {
+                let server_name = stream.get_ref().1.server_name().filter(|_| tcp.sni_routing).map(str::to_ascii_lowercase);
+                forward_tcp(stream, client_addr, server_name, Vec::new(), lb, tcp).await;
             }
+            Ok(Err(e)) => log_warn(format!("TLS handshake with {} failed: {}", client_addr, e)),
+            Err(_) => log_warn(format!("TLS handshake with {} timed out", client_addr)),
         }
+        return;
+    }
 
-        lb.decrement_connection(backend).await; // Decrement connection count
+    // With SNI routing the ClientHello is read up front and replayed to the backend later
+    let (server_name, client_hello) = if tcp.sni_routing {
+        read_client_hello(&mut inbound).await
     } else {
-        eprintln!("No available backends to handle TCP request.");
+        (None, Vec::new())
+    };
+    forward_tcp(inbound, client_addr, server_name, client_hello, lb, tcp).await;
+}
+
+// How often a client held by no_backend_wait checks for an available backend again
+const NO_BACKEND_POLL: Duration = Duration::from_millis(50);
+
+// Select a TCP backend, from the group named by the SNI host name when there is one
+async fn select_tcp_backend(lb: &LoadBalancer, client_addr: SocketAddr, server_name: Option<&str>, sni_group: Option<&str>, excluded: &[SocketAddr]) -> Option<Backend> {
+    let Some(group) = sni_group else {
+        return lb.next_backend_for_client(client_addr.ip(), Protocol::TCP, excluded).await;
+    };
+
+    match lb.next_backend_in_group(group, Protocol::TCP, excluded).await {
+        Some(backend) => {
+            let server_name = server_name.unwrap_or_default();
+            log_event(
+                LogLevel::Debug,
+                "sni_route",
+                format!("Routing TLS connection for {} to group {}", server_name, group),
+                &[("client", json!(client_addr.to_string())), ("sni", json!(server_name)), ("group", json!(group))],
+            );
+            Some(backend)
+        }
+        None => {
+            log_debug(format!("No backend of group {} available for SNI, falling back to regular selection", group));
+            lb.next_backend_for_client(client_addr.ip(), Protocol::TCP, excluded).await
+        }
+    }
+}
+
+// Why a TCP connection ended, as reported in the access log
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CloseReason {
+    Clean,
+    IoError,
+    IdleTimeout,
+    ConnectFailed,
+    NoBackend,
+}
+
+impl CloseReason {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CloseReason::Clean => "clean",
+            CloseReason::IoError => "io_error",
+            CloseReason::IdleTimeout => "idle_timeout",
+            CloseReason::ConnectFailed => "connect_failed",
+            CloseReason::NoBackend => "no_backend",
+        }
+    }
+}
+
+// One summary line per finished TCP connection, for billing and debugging
+fn log_access(tcp: &TcpConfig, client_addr: SocketAddr, backend: Option<SocketAddr>, bytes: (u64, u64), started: Instant, reason: CloseReason) {
+    if !tcp.access_log {
+        return;
+    }
+    let backend = backend.map(|addr| addr.to_string());
+    let duration_ms = started.elapsed().as_millis() as u64;
+    log_event(
+        LogLevel::Info,
+        "access",
+        format!(
+            "Access {} -> {} up={} down={} duration_ms={} reason={}",
+            client_addr,
+            backend.as_deref().unwrap_or("-"),
+            bytes.0,
+            bytes.1,
+            duration_ms,
+            reason.as_str()
+        ),
+        &[
+            ("client", json!(client_addr.to_string())),
+            ("backend", json!(backend)),
+            ("bytes_up", json!(bytes.0)),
+            ("bytes_down", json!(bytes.1)),
+            ("duration_ms", json!(duration_ms)),
+            ("reason", json!(reason.as_str())),
+        ],
+    );
+}
+
+// Select a backend and relay the (possibly decrypted) client stream to it. `client_hello` holds
+// bytes already read from the client that have to reach the backend first.
+async fn forward_tcp<S>(inbound: S, client_addr: SocketAddr, server_name: Option<String>, client_hello: Vec<u8>, lb: Arc<LoadBalancer>, tcp: Arc<TcpConfig>)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let started = Instant::now();
+    let sni_group = match &server_name {
+        Some(name) => lb.find_group(name).await,
+        None => None,
+    };
+
+    // Connect to a backend, on failure try other backends up to `retries` more times
+    let mut failed: Vec<SocketAddr> = Vec::new();
+    let mut skipped: Vec<SocketAddr> = Vec::new();  // Half-open backends whose trial connection another client took
+    let wait_until = tcp.no_backend_wait.map(|wait| started + wait);
+    let (backend, mut outbound, _connection) = loop {
+        let excluded: Vec<SocketAddr> = failed.iter().chain(&skipped).copied().collect();
+        let Some(backend) = select_tcp_backend(&lb, client_addr, server_name.as_deref(), sni_group.as_deref(), &excluded).await else {
+            // A rolling restart leaves no backend only briefly, hold the client until one is back
+            if let Some(until) = wait_until.filter(|until| failed.is_empty() && Instant::now() < *until && !lb.is_draining()) {
+                sleep(NO_BACKEND_POLL.min(until - Instant::now())).await;
+                continue;
+            }
+            if !failed.is_empty() {
+                log_warn(format!("No further backend to retry, dropping TCP connection from {}", client_addr));
+            } else if lb.is_saturated(Protocol::TCP).await {
+                log_warn(format!("All backends are at their connection limit, rejecting TCP connection from {}", client_addr));
+            } else {
+                log_error("No available backends to handle TCP request.".to_string());
+            }
+            let reason = if failed.is_empty() { CloseReason::NoBackend } else { CloseReason::ConnectFailed };
+            log_access(&tcp, client_addr, failed.last().copied(), (0, 0), started, reason);
+            return;
+        };
+
+        log_event(
+            LogLevel::Debug,
+            "tcp_forward",
+            format!("Forwarding TCP connection to backend: {} (Protocol: {:?})", backend.addr, backend.protocol),
+            &[("client", json!(client_addr.to_string())), ("backend", json!(backend.addr.to_string()))],
+        );
+        if backend.protocol == Protocol::UDP {
+            log_error(format!("Received a TCP connection, but backend expects UDP for backend: {}", backend.addr));
+            log_access(&tcp, client_addr, Some(backend.addr), (0, 0), started, CloseReason::NoBackend);
+            return;
+        }
+
+        if !lb.begin_connect(backend.addr).await {
+            skipped.push(backend.addr);
+            continue;
+        }
+
+        // A failed attempt no longer counts against the backend once the guard is dropped
+        let connection = lb.track_connection(backend).await;
+
+        // Prefer a pre-connected socket and top the pool up again in the background
+        if let Some(pool) = &tcp.pool {
+            let (pooled, short) = pool.take(backend.addr).await;
+            if short {
+                tokio::spawn(pool.clone().refill(backend.addr));
+            }
+            if let Some(outbound) = pooled {
+                lb.record_connect(backend.addr, true).await;
+                break (backend, outbound, connection);
+            }
+        }
+
+        let connect_started = Instant::now();
+        let error = match timeout(tcp.connect_timeout, connect_tcp(backend.addr, lb.source_addr)).await {
+            Ok(Ok(outbound)) => {
+                lb.record_latency(backend.addr, connect_started.elapsed()).await;
+                lb.record_connect(backend.addr, true).await;
+                if let Err(e) = configure_tcp_stream(&outbound, tcp.nodelay, tcp.keepalive) {
+                    log_warn(format!("Failed to set socket options for backend {}: {:?}", backend.addr, e));
+                }
+                break (backend, outbound, connection);
+            }
+            Ok(Err(e)) => format!("{:?}", e),
+            Err(_) => format!("timed out after {} ms", tcp.connect_timeout.as_millis()),
+        };
+
+        log_warn(format!("Failed to connect to backend: {}. Error: {}", backend.addr, error));
+        lb.record_failure(backend).await;
+        lb.record_connect(backend.addr, false).await;
+        drop(connection);
+        failed.push(backend.addr);
+        if failed.len() > tcp.retries as usize {
+            log_warn(format!("Giving up on TCP connection from {} after {} failed attempts", client_addr, failed.len()));
+            log_access(&tcp, client_addr, Some(backend.addr), (0, 0), started, CloseReason::ConnectFailed);
+            return;
+        }
+    };
+
+    // The header has to be the very first bytes the backend receives
+    if let Some(version) = tcp.proxy_protocol {
+        let header = proxy_header(version, client_addr, backend.addr);
+        if let Err(e) = outbound.write_all(&header).await {
+            log_warn(format!("Failed to send PROXY protocol header to backend {}: {:?}", backend.addr, e));
+            lb.record_failure(backend).await;
+            log_access(&tcp, client_addr, Some(backend.addr), (0, 0), started, CloseReason::IoError);
+            return;
+        }
+    }
+
+    if !client_hello.is_empty() {
+        if let Err(e) = outbound.write_all(&client_hello).await {
+            log_warn(format!("Failed to replay ClientHello to backend {}: {:?}", backend.addr, e));
+            lb.record_failure(backend).await;
+            log_access(&tcp, client_addr, Some(backend.addr), (0, 0), started, CloseReason::IoError);
+            return;
+        }
+        Metrics::add(&lb.metrics.bytes_client_to_backend, client_hello.len() as u64);
+        lb.add_backend_bytes(backend.addr, client_hello.len() as u64, 0).await;
     }
+
+    let (ri, wi) = split(inbound);
+    let (ro, wo) = split(outbound);
+
+    Metrics::add(&lb.metrics.connections_total, 1);
+
+    let activity = Arc::new(Activity::new());
+    let bytes_up = Arc::new(AtomicU64::new(0));
+    let bytes_down = Arc::new(AtomicU64::new(0));
+
+    // Response codes are only needed for outlier detection, which is meant for HTTP backends
+    let http_status = (lb.health.outlier_5xx_ratio > 0.0).then(|| Arc::new(AtomicU16::new(0)));
+
+    let client_to_server = tokio::spawn(copy_tracked(ri, wo, activity.clone(), bytes_up.clone(), None, "client to server"));
+    let server_to_client = tokio::spawn(copy_tracked(ro, wi, activity.clone(), bytes_down.clone(), http_status.clone(), "server to client"));
+    let aborts = [client_to_server.abort_handle(), server_to_client.abort_handle()];
+
+    // Dropping both copy tasks closes both sockets, which ends an idle connection
+    let reason = tokio::select! {
+        result = async { tokio::try_join!(client_to_server, server_to_client) } => match result {
+            Ok((None, None)) => CloseReason::Clean,
+            Ok((up, down)) => {
+                // Writing to or reading from the backend failed mid-stream
+                if up == Some(CopyFailure::Write) || down == Some(CopyFailure::Read) {
+                    lb.record_failure(backend).await;
+                }
+                CloseReason::IoError
+            }
+            Err(e) => {
+                log_error(format!("Error joining copy tasks: {:?}", e));
+                CloseReason::IoError
+            }
+        },
+        _ = wait_for_idle(&activity, tcp.idle_timeout) => {
+            log_event(
+                LogLevel::Debug,
+                "tcp_idle_timeout",
+                format!("Closing idle TCP connection from {} to backend {}", client_addr, backend.addr),
+                &[("client", json!(client_addr.to_string())), ("backend", json!(backend.addr.to_string()))],
+            );
+            aborts.iter().for_each(|abort| abort.abort());
+            CloseReason::IdleTimeout
+        }
+    };
+
+    if let Some(status) = http_status.map(|status| status.load(Ordering::Relaxed)).filter(|&status| status != 0) {
+        lb.record_response(backend, status).await;
+    }
+
+    let (bytes_up, bytes_down) = (bytes_up.load(Ordering::Relaxed), bytes_down.load(Ordering::Relaxed));
+    Metrics::add(&lb.metrics.bytes_client_to_backend, bytes_up);
+    Metrics::add(&lb.metrics.bytes_backend_to_client, bytes_down);
+    lb.add_backend_bytes(backend.addr, bytes_up, bytes_down).await;
+    log_event(
+        LogLevel::Debug,
+        "tcp_closed",
+        format!(
+            "Closed TCP connection from {} to backend {} ({} bytes sent, {} bytes received)",
+            client_addr, backend.addr, bytes_up, bytes_down
+        ),
+        &[
+            ("client", json!(client_addr.to_string())),
+            ("backend", json!(backend.addr.to_string())),
+            ("bytes_up", json!(bytes_up)),
+            ("bytes_down", json!(bytes_down)),
+        ],
+    );
+    log_access(&tcp, client_addr, Some(backend.addr), (bytes_up, bytes_down), started, reason);
+}
+
+// Behavior of the UDP listener
+#[derive(Clone)]
+pub struct UdpConfig {
+    pub session_timeout: Duration,  // Close a client session without traffic in either direction for this long
+    pub batch_size: usize,  // Datagrams received per recvmmsg call on Linux, 1 receives them one at a time
+    pub max_datagram: usize,  // Receive buffer of each datagram, larger ones are cut to this size
+    pub max_sessions: Option<usize>,  // Sessions (each with an outbound socket) open at once, datagrams of further clients are dropped
 }
 
-pub async fn handle_udp(socket: Arc<UdpSocket>, lb: Arc<LoadBalancer>) {
-    let mut buf = vec![0; 1024];
+// Client datagrams waiting for the task of their session, further ones are dropped while it is full
+const UDP_SESSION_QUEUE: usize = 64;
 
+// A UDP client pinned to a backend, with its own outbound socket connected to that backend
+struct UdpSession {
+    backend: Backend,
+    datagrams: mpsc::Sender<Vec<u8>>,  // Client datagrams for the session task to send to the backend
+    activity: Arc<Activity>,
+    task: AbortHandle,  // Task exchanging datagrams between client and backend
+    _connection: ConnectionGuard,  // Counts the session as one connection to its backend
+}
+
+// Bind the outbound socket of a new session once and hand it to a task of its own, so a backend
+// that is slow to take datagrams never holds up the receive loop of the listener
+async fn open_udp_session(lb: &Arc<LoadBalancer>, listener: Arc<UdpSocket>, client: SocketAddr, backend: Backend, max_datagram: usize) -> std::io::Result<UdpSession> {
+    let socket = UdpSocket::bind(local_addr_for(&backend.addr, lb.source_addr)).await?;
+    // Connecting makes the kernel drop datagrams from any other source, so nobody
+    // but the backend can inject responses through the ephemeral port
+    socket.connect(backend.addr).await?;
+
+    let activity = Arc::new(Activity::new());
+    let (datagrams, queued) = mpsc::channel(UDP_SESSION_QUEUE);
+    let task = tokio::spawn(run_udp_session(listener, socket, client, backend.addr, queued, activity.clone(), max_datagram)).abort_handle();
+    let _connection = lb.track_connection(backend).await;
+    Ok(UdpSession { backend, datagrams, activity, task, _connection })
+}
+
+// Send queued client datagrams to the backend and forward every datagram of the backend to the
+// client, responses as well as backend-initiated packets
+async fn run_udp_session(
+    listener: Arc<UdpSocket>,
+    socket: UdpSocket,
+    client: SocketAddr,
+    backend: SocketAddr,
+    mut queued: mpsc::Receiver<Vec<u8>>,
+    activity: Arc<Activity>,
+    max_datagram: usize,
+) {
+    let mut buf = vec![0; max_datagram];
     loop {
-        if let Ok((len, addr)) = socket.recv_from(&mut buf).await {
-            let backend = {
-                lb.next_backend().await
-            };
+        tokio::select! {
+            datagram = queued.recv() => {
+                let Some(datagram) = datagram else { break };
+                if let Err(e) = socket.send(&datagram).await {
+                    log_warn(format!("Failed to send UDP packet to backend {}: {:?}", backend, e));
+                }
+            }
+            received = socket.recv(&mut buf) => match received {
+                Ok(len) => {
+                    if let Err(e) = listener.send_to(&buf[..len], client).await {
+                        log_warn(format!("Failed to send UDP response to {}: {:?}", client, e));
+                    }
+                    activity.touch();
+                }
+                // Usually an ICMP error for an earlier datagram, later ones may still get an answer
+                Err(e) => log_debug(format!("Error receiving UDP response for {}: {:?}", client, e)),
+            },
+        }
+    }
+}
 
-            if let Some(backend) = backend {
-                log(format!("Forwarding UDP packet to backend: {} (Protocol: {:?})", backend.addr, backend.protocol));
-                lb.increment_connection(backend).await; // Increment connection count
-
-                match backend.protocol {
-                    Protocol::UDP => {
-                        if let Ok(backend_socket) = UdpSocket::bind("0.0.0.0:0").await {
-                            if let Err(e) = backend_socket.send_to(&buf[..len], backend.addr).await {
-                                eprintln!("Failed to send UDP packet to backend {}: {:?}", backend.addr, e);
-                            }
-                            let mut response_buf = vec![0; 1024];
-                            if let Ok((resp_len, _)) = backend_socket.recv_from(&mut response_buf).await {
-                                if let Err(e) = socket.send_to(&response_buf[..resp_len], addr).await {
-                                    eprintln!("Failed to send UDP response to {}: {:?}", addr, e);
-                                }
-                            }
-                        } else {
-                            eprintln!("Failed to bind temporary UDP socket");
-                        }
+fn close_udp_session(client: SocketAddr, session: UdpSession) {
+    session.task.abort();
+    log_event(
+        LogLevel::Debug,
+        "udp_session_closed",
+        format!("Closed UDP session from {} to backend {}", client, session.backend.addr),
+        &[("client", json!(client.to_string())), ("backend", json!(session.backend.addr.to_string()))],
+    );
+}
+
+// Every client gets a session that counts as one connection to its backend until it goes idle or shutdown is requested
+pub async fn handle_udp(socket: Arc<UdpSocket>, lb: Arc<LoadBalancer>, udp: UdpConfig, mut shutdown: watch::Receiver<bool>) {
+    let mut batch = UdpBatch::new(udp.batch_size, udp.max_datagram);
+    let mut sessions: HashMap<SocketAddr, UdpSession> = HashMap::new();
+    let mut eviction = interval(Duration::from_secs(1));
+    let mut dropped: u64 = 0;  // Datagrams of new clients dropped at the session limit since the last report
+
+    loop {
+        let received = tokio::select! {
+            received = batch.recv(&socket) => match received {
+                Ok(count) => count,
+                Err(_) => {
+                    log_warn("Failed to receive UDP packet".to_string());
+                    continue;
+                }
+            },
+            _ = eviction.tick() => {
+                let idle: Vec<SocketAddr> = sessions
+                    .iter()
+                    .filter(|(_, session)| session.activity.idle() >= udp.session_timeout)
+                    .map(|(addr, _)| *addr)
+                    .collect();
+                for addr in idle {
+                    if let Some(session) = sessions.remove(&addr) {
+                        close_udp_session(addr, session);
                     }
-                    Protocol::TCP => {
-                        eprintln!("Received a UDP packet, but backend expects TCP for backend: {}", backend.addr);
+                }
+                // Reported once per tick, a flood would otherwise produce a log line per datagram
+                if let Some(max) = udp.max_sessions.filter(|_| dropped > 0) {
+                    log_warn(format!("Dropped {} UDP datagrams from new clients, the limit of {} sessions is reached", dropped, max));
+                    dropped = 0;
+                }
+                continue;
+            },
+            _ = shutdown.changed() => break,
+        };
+
+        for index in 0..received {
+            let (data, addr) = batch.get(index);
+
+            // Keep the session as long as its backend is still active, move the client to a new one otherwise
+            if let Some(session) = sessions.get(&addr) {
+                if !lb.is_active(&session.backend).await {
+                    if let Some(session) = sessions.remove(&addr) {
+                        close_udp_session(addr, session);
                     }
                 }
+            }
 
-                lb.decrement_connection(backend).await; // Decrement connection count
-            } else {
-                eprintln!("No available backends to handle UDP request.");
+            let sessions_open = sessions.len();
+            let session = match sessions.entry(addr) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    if udp.max_sessions.is_some_and(|max| sessions_open >= max) {
+                        dropped += 1;
+                        continue;
+                    }
+                    let Some(backend) = lb.next_backend_for_client(addr.ip(), Protocol::UDP, &[]).await else {
+                        log_error("No available backends to handle UDP request.".to_string());
+                        continue;
+                    };
+                    match open_udp_session(&lb, socket.clone(), addr, backend, udp.max_datagram).await {
+                        Ok(session) => entry.insert(session),
+                        Err(e) => {
+                            log_error(format!("Failed to open UDP socket to backend {}: {:?}", backend.addr, e));
+                            continue;
+                        }
+                    }
+                }
+            };
+            log_event(
+                LogLevel::Debug,
+                "udp_forward",
+                format!("Forwarding UDP packet to backend: {} (Protocol: {:?})", session.backend.addr, session.backend.protocol),
+                &[("client", json!(addr.to_string())), ("backend", json!(session.backend.addr.to_string())), ("bytes", json!(data.len()))],
+            );
+            session.activity.touch();
+            match session.datagrams.try_send(data.to_vec()) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => log_debug(format!("UDP queue to backend {} is full, dropping packet from {}", session.backend.addr, addr)),
+                Err(TrySendError::Closed(_)) => log_warn(format!("UDP session task for {} has stopped, dropping packet", addr)),
             }
-        } else {
-            eprintln!("Failed to receive UDP packet");
         }
     }
+
+    for (addr, session) in sessions.drain() {
+        close_udp_session(addr, session);
+    }
 }
@@ -0,0 +1,162 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, SocketAddr};
+use crate::modules::load_balancer::Backend;
+
+const VIRTUAL_NODES: u32 = 100; // Points placed on the ring per unit of backend weight
+const MAGLEV_TABLE_SIZE: usize = 65537; // Prime, so every skip value walks the whole table
+
+// Consistent hash ring over a set of backends. Each backend owns several points on the
+// ring, a key is served by the first point clockwise from its hash. Removing a backend
+// only moves the keys that landed on its points, every other key keeps its backend.
+pub struct HashRing {
+    members: Vec<(SocketAddr, u32)>,
+    points: Vec<(u64, Backend)>,
+}
+
+impl HashRing {
+    pub fn new(backends: &[Backend]) -> Self {
+        let mut members: Vec<(SocketAddr, u32)> = backends.iter().map(|b| (b.addr, b.weight)).collect();
+        members.sort();
+
+        let mut points = Vec::new();
+        for backend in backends {
+            for replica in 0..VIRTUAL_NODES * backend.weight {
+                points.push((hash_of(&(backend.addr, replica)), *backend));
+            }
+        }
+        points.sort_by_key(|(point, _)| *point);
+
+        HashRing { members, points }
+    }
+
+    // Whether the ring was built from exactly this set of backends and weights
+    pub fn matches(&self, backends: &[Backend]) -> bool {
+        let mut members: Vec<(SocketAddr, u32)> = backends.iter().map(|b| (b.addr, b.weight)).collect();
+        members.sort();
+        self.members == members
+    }
+
+    pub fn get(&self, ip: IpAddr) -> Option<Backend> {
+        if self.points.is_empty() {
+            return None;
+        }
+
+        let key = hash_of(&ip);
+        let idx = self.points.partition_point(|(point, _)| *point < key);
+        Some(self.points[idx % self.points.len()].1)
+    }
+}
+
+// Maglev lookup table (Eisenbud et al., NSDI 2016). Every backend walks the table in its own
+// pseudo-random permutation and the backends take turns claiming the next free slot of their
+// permutation, `weight` slots per turn. A key is served by the slot its hash lands on, so each
+// backend owns an almost equal share of slots and losing one backend moves about 1/N of the keys.
+pub struct MaglevTable {
+    members: Vec<(SocketAddr, u32)>,
+    slots: Vec<Backend>,
+}
+
+impl MaglevTable {
+    pub fn new(backends: &[Backend]) -> Self {
+        let mut sorted = backends.to_vec();
+        sorted.sort_by_key(|b| b.addr);
+        let members: Vec<(SocketAddr, u32)> = sorted.iter().map(|b| (b.addr, b.weight)).collect();
+
+        // Permutation of each backend: offset + j * skip, skip is never 0
+        let size = MAGLEV_TABLE_SIZE as u64;
+        let permutations: Vec<(usize, usize)> = sorted
+            .iter()
+            .map(|b| {
+                let offset = hash_of(&(b.addr, "offset")) % size;
+                let skip = hash_of(&(b.addr, "skip")) % (size - 1) + 1;
+                (offset as usize, skip as usize)
+            })
+            .collect();
+
+        let mut owners: Vec<Option<usize>> = vec![None; MAGLEV_TABLE_SIZE];
+        let mut next = vec![0usize; sorted.len()];
+        let mut filled = 0;
+        'fill: while filled < MAGLEV_TABLE_SIZE && !sorted.is_empty() {
+            for (i, backend) in sorted.iter().enumerate() {
+                let (offset, skip) = permutations[i];
+                for _ in 0..backend.weight.max(1) {
+                    let mut slot = (offset + next[i] * skip) % MAGLEV_TABLE_SIZE;
+                    while owners[slot].is_some() {
+                        next[i] += 1;
+                        slot = (offset + next[i] * skip) % MAGLEV_TABLE_SIZE;
+                    }
+                    owners[slot] = Some(i);
+                    next[i] += 1;
+                    filled += 1;
+                    if filled == MAGLEV_TABLE_SIZE {
+                        break 'fill;
+                    }
+                }
+            }
+        }
+
+        let slots = owners.into_iter().flatten().map(|i| sorted[i]).collect();
+        MaglevTable { members, slots }
+    }
+
+    // Whether the table was built from exactly this set of backends and weights
+    pub fn matches(&self, backends: &[Backend]) -> bool {
+        let mut members: Vec<(SocketAddr, u32)> = backends.iter().map(|b| (b.addr, b.weight)).collect();
+        members.sort();
+        self.members == members
+    }
+
+    pub fn get(&self, ip: IpAddr) -> Option<Backend> {
+        if self.slots.is_empty() {
+            return None;
+        }
+        Some(self.slots[(hash_of(&ip) % self.slots.len() as u64) as usize])
+    }
+}
+
+pub fn hash_of<T: Hash>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::load_balancer::Protocol;
+
+    const BACKENDS: usize = 10;
+    const KEYS: u32 = 20_000;
+
+    fn backends(count: usize) -> Vec<Backend> {
+        (0..count)
+            .map(|i| Backend { addr: SocketAddr::from(([10, 0, 0, i as u8 + 1], 5432)), protocol: Protocol::TCP, weight: 1 })
+            .collect()
+    }
+
+    // Share of the keys served by another backend once the last of BACKENDS backends is gone
+    fn moved_fraction(before: impl Fn(IpAddr) -> Option<Backend>, after: impl Fn(IpAddr) -> Option<Backend>) -> f64 {
+        let moved = (0..KEYS)
+            .map(|key| IpAddr::from([172, 16, (key >> 8) as u8, key as u8]))
+            .filter(|&ip| before(ip).map(|b| b.addr) != after(ip).map(|b| b.addr))
+            .count();
+        moved as f64 / KEYS as f64
+    }
+
+    #[test]
+    fn hash_ring_removal_moves_about_one_nth_of_keys() {
+        let before = HashRing::new(&backends(BACKENDS));
+        let after = HashRing::new(&backends(BACKENDS - 1));
+        let moved = moved_fraction(|ip| before.get(ip), |ip| after.get(ip));
+        assert!(moved <= 1.0 / BACKENDS as f64 + 0.03, "{:.3} of the keys moved", moved);
+    }
+
+    #[test]
+    fn maglev_removal_moves_about_one_nth_of_keys() {
+        let before = MaglevTable::new(&backends(BACKENDS));
+        let after = MaglevTable::new(&backends(BACKENDS - 1));
+        let moved = moved_fraction(|ip| before.get(ip), |ip| after.get(ip));
+        assert!(moved <= 1.0 / BACKENDS as f64 + 0.03, "{:.3} of the keys moved", moved);
+    }
+}
@@ -0,0 +1,270 @@
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use chrono::{DateTime, Local};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UdpSocket;
+use crate::modules::logging::log_warn;
+use crate::modules::sockets::{connect_tcp, local_addr_for};
+use tokio::time::{timeout, Duration, Instant};
+
+// Settings shared by all active health checks
+#[derive(Debug, Clone)]
+pub struct HealthCheckConfig {
+    pub interval: Duration,  // Time between two health-check sweeps
+    pub jitter: f64,         // Random share by which each interval is lengthened or shortened
+    pub timeout: Duration,   // Upper bound for a single probe
+    pub concurrency: usize,  // Maximum number of probes in flight at once
+    pub rise: u32,           // Consecutive successes before a backend becomes active
+    pub fall: u32,           // Consecutive failures before a backend becomes inactive
+    pub http_path: Option<String>,  // Probe TCP backends with an HTTP GET to this path instead of a plain connect
+    pub grpc_service: Option<String>,  // Probe TCP backends with a gRPC health check for this service, "" for the whole server
+    pub udp_payload: Option<Vec<u8>>,  // Datagram sent to UDP backends, setting it requires a reply
+    pub udp_expect: Option<Vec<u8>>,  // Bytes a UDP reply must contain to count as healthy
+    pub passive_failures: u32,  // Failed forwards within the window that eject a backend, 0 disables passive checks
+    pub passive_window: Duration,
+    pub outlier_5xx_ratio: f64,  // Share of 5xx responses within the window that ejects a backend, 0 disables outlier detection
+    pub outlier_window: Duration,
+    pub outlier_cooldown: Duration,  // Time an outlier stays out of rotation before health checks may bring it back
+    pub circuit_failures: u32,  // Consecutive failed connects that open a backend's circuit, 0 disables the circuit breaker
+    pub circuit_cooldown: Duration,  // Time an open circuit is skipped before a trial connection is let through
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        HealthCheckConfig {
+            interval: Duration::from_secs(10),
+            jitter: 0.1,
+            timeout: Duration::from_secs(3),
+            concurrency: 32,
+            rise: 1,
+            fall: 3,
+            http_path: None,
+            grpc_service: None,
+            udp_payload: None,
+            udp_expect: None,
+            passive_failures: 0,
+            passive_window: Duration::from_secs(30),
+            outlier_5xx_ratio: 0.0,
+            outlier_window: Duration::from_secs(30),
+            outlier_cooldown: Duration::from_secs(30),
+            circuit_failures: 0,
+            circuit_cooldown: Duration::from_secs(10),
+        }
+    }
+}
+
+impl HealthCheckConfig {
+    // UDP probes need a reply once a payload or an expected reply is configured, otherwise silence passes
+    pub fn expects_udp_reply(&self) -> bool {
+        self.udp_payload.is_some() || self.udp_expect.is_some()
+    }
+}
+
+// Consecutive probe outcomes of a single backend, only one of both counters is non-zero
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HealthStreak {
+    pub successes: u32,
+    pub failures: u32,
+}
+
+impl HealthStreak {
+    pub fn record(&mut self, healthy: bool) {
+        if healthy {
+            self.successes += 1;
+            self.failures = 0;
+        } else {
+            self.failures += 1;
+            self.successes = 0;
+        }
+    }
+}
+
+// Wall-clock times of a backend's last transitions, shown in the UDS status
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HealthTransitions {
+    pub healthy: Option<DateTime<Local>>,
+    pub unhealthy: Option<DateTime<Local>>,
+}
+
+// Circuit breaker state of a TCP backend, driven by the outcome of forwarded connects
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Circuit {
+    Closed { failures: u32 },  // Consecutive failed connects so far
+    Open { since: Instant },   // Skipped by selection until the cooldown passed
+    HalfOpen,                  // A single trial connect is in flight, its outcome closes or reopens the circuit
+}
+
+impl fmt::Display for Circuit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Circuit::Closed { .. } => write!(f, "closed"),
+            Circuit::Open { .. } => write!(f, "open"),
+            Circuit::HalfOpen => write!(f, "half-open"),
+        }
+    }
+}
+
+const LATENCY_SMOOTHING: f64 = 0.3; // Weight of a new sample in the moving average
+const LATENCY_HALF_LIFE: Duration = Duration::from_secs(30); // Age at which a measurement counts half
+const MAX_STATUS_LINE: u64 = 512; // Bytes of an HTTP health response read before the check fails without a status line
+
+// Exponentially weighted moving average of a backend's connect latency. The average fades
+// towards zero while no new samples arrive, so a backend that was slow once gets tried again.
+#[derive(Debug, Clone, Copy)]
+pub struct Latency {
+    millis: f64,
+    updated: Instant,
+}
+
+impl Latency {
+    pub fn new(sample: Duration) -> Self {
+        Latency { millis: sample.as_secs_f64() * 1000.0, updated: Instant::now() }
+    }
+
+    pub fn record(&mut self, sample: Duration) {
+        let current = self.millis();
+        self.millis = current + LATENCY_SMOOTHING * (sample.as_secs_f64() * 1000.0 - current);
+        self.updated = Instant::now();
+    }
+
+    // Smoothed latency in milliseconds, halved for every half-life since the last sample
+    pub fn millis(&self) -> f64 {
+        let age = self.updated.elapsed().as_secs_f64() / LATENCY_HALF_LIFE.as_secs_f64();
+        self.millis * 0.5f64.powf(age)
+    }
+}
+
+// Why a probe failed, so the log and the UDS status tell a stopped backend from a slow or unreachable one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProbeError {
+    Refused,      // Nothing listens on the port, usually the backend process is down
+    Timeout,      // No answer within the probe timeout, network trouble or an overloaded backend
+    Unreachable,  // No route to the host or network
+    HttpStatus(u16),  // The health endpoint answered with a non-2xx status
+    BadResponse,  // The answer did not start with an HTTP status line
+    UnexpectedReply,  // A UDP reply arrived but did not contain the expected bytes
+    #[cfg(feature = "grpc")]
+    GrpcStatus(u32),  // The gRPC health check failed with a non-OK status, e.g. 5 (NOT_FOUND) for an unknown service
+    #[cfg(feature = "grpc")]
+    NotServing(u64),  // The gRPC health check answered with another status than SERVING
+    Io(io::ErrorKind),
+}
+
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProbeError::Refused => write!(f, "connection refused"),
+            ProbeError::Timeout => write!(f, "timed out"),
+            ProbeError::Unreachable => write!(f, "host unreachable"),
+            ProbeError::HttpStatus(status) => write!(f, "HTTP status {}", status),
+            ProbeError::BadResponse => write!(f, "invalid HTTP response"),
+            ProbeError::UnexpectedReply => write!(f, "unexpected reply"),
+            #[cfg(feature = "grpc")]
+            ProbeError::GrpcStatus(status) => write!(f, "gRPC status {}", status),
+            #[cfg(feature = "grpc")]
+            ProbeError::NotServing(status) => match status {
+                2 => write!(f, "gRPC NOT_SERVING"),
+                3 => write!(f, "gRPC SERVICE_UNKNOWN"),
+                _ => write!(f, "gRPC serving status {}", status),
+            },
+            ProbeError::Io(kind) => write!(f, "{}", kind),
+        }
+    }
+}
+
+impl From<io::Error> for ProbeError {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::ConnectionRefused => ProbeError::Refused,
+            io::ErrorKind::TimedOut => ProbeError::Timeout,
+            io::ErrorKind::HostUnreachable | io::ErrorKind::NetworkUnreachable => ProbeError::Unreachable,
+            kind => ProbeError::Io(kind),
+        }
+    }
+}
+
+// A TCP backend is healthy if the handshake completes within the probe timeout
+pub async fn probe_tcp(addr: SocketAddr, source: Option<IpAddr>, probe_timeout: Duration) -> Result<(), ProbeError> {
+    match timeout(probe_timeout, connect_tcp(addr, source)).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => Err(e.into()),
+        Err(_) => Err(ProbeError::Timeout),
+    }
+}
+
+// An HTTP backend is healthy if a GET to the configured path answers with a 2xx status.
+// Only the status line is read, at most MAX_STATUS_LINE bytes of it, the connection is closed right after.
+pub async fn probe_http(addr: SocketAddr, source: Option<IpAddr>, path: &str, probe_timeout: Duration) -> Result<(), ProbeError> {
+    let probe = async {
+        let mut stream = connect_tcp(addr, source).await?;
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: SideLB/{}\r\nConnection: close\r\n\r\n",
+            path,
+            addr,
+            env!("CARGO_PKG_VERSION")
+        );
+        stream.write_all(request.as_bytes()).await?;
+
+        let mut reader = BufReader::new((&mut stream).take(MAX_STATUS_LINE));
+        let mut status_line = String::new();
+        reader.read_line(&mut status_line).await?;
+        drop(reader);
+        let _ = stream.shutdown().await;
+        // A line cut off by the limit (or the end of the stream) is no status line
+        if !status_line.ends_with('\n') {
+            return Ok(None);
+        }
+
+        Ok::<_, ProbeError>(parse_status_code(&status_line))
+    };
+
+    match timeout(probe_timeout, probe).await {
+        Ok(Ok(Some(status))) if (200..300).contains(&status) => Ok(()),
+        Ok(Ok(Some(status))) => Err(ProbeError::HttpStatus(status)),
+        Ok(Ok(None)) => Err(ProbeError::BadResponse),
+        Ok(Err(e)) => Err(e),
+        Err(_) => Err(ProbeError::Timeout),
+    }
+}
+
+// Extract the status code from a line like `HTTP/1.1 200 OK`
+pub fn parse_status_code(status_line: &str) -> Option<u16> {
+    let mut parts = status_line.split_whitespace();
+    if !parts.next()?.starts_with("HTTP/") {
+        return None;
+    }
+    parts.next()?.parse().ok()
+}
+
+// UDP has no handshake, so the probe sends a datagram on a connected socket and waits up to the probe
+// timeout for what comes back. An ICMP port unreachable surfaces as a refused receive and fails the probe
+// in every mode. Silence means the port is open or filtered, which only fails a probe with a payload or
+// expected reply: then a reply (containing the expected bytes) has to arrive.
+pub async fn probe_udp(addr: SocketAddr, source: Option<IpAddr>, health: &HealthCheckConfig) -> Result<(), ProbeError> {
+    let udp_socket = match UdpSocket::bind(local_addr_for(&addr, source)).await {
+        Ok(udp_socket) => udp_socket,
+        Err(e) => {
+            log_warn(format!("Failed to bind UDP socket for health check on backend {}", addr));
+            return Err(e.into());
+        }
+    };
+    let payload = health.udp_payload.as_deref().unwrap_or(b"health-check");
+    let probe = async {
+        udp_socket.connect(addr).await?;
+        udp_socket.send(payload).await?;
+        let mut reply = vec![0u8; 65536];
+        let len = udp_socket.recv(&mut reply).await?;
+        Ok::<_, ProbeError>(reply[..len].to_vec())
+    };
+
+    match timeout(health.timeout, probe).await {
+        Ok(Ok(reply)) => match &health.udp_expect {
+            Some(expect) if !reply.windows(expect.len()).any(|window| window == expect.as_slice()) => Err(ProbeError::UnexpectedReply),
+            _ => Ok(()),
+        },
+        Ok(Err(e)) => Err(e),
+        Err(_) if health.expects_udp_reply() => Err(ProbeError::Timeout),
+        Err(_) => Ok(()),
+    }
+}
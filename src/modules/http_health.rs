@@ -0,0 +1,64 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{timeout, Duration};
+use crate::modules::load_balancer::LoadBalancer;
+use crate::modules::logging::{log, log_error, log_warn};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5); // Time a client has to send its request
+
+// Serve `GET /healthz` for orchestrators that probe over HTTP, with the same verdict as the UDS health check
+pub async fn run_http_health_server(addr: SocketAddr, lb: Arc<LoadBalancer>) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            log_error(format!("Failed to bind HTTP health endpoint on {}: {:?}", addr, e));
+            return;
+        }
+    };
+    log(format!("HTTP health endpoint started on: http://{}/healthz", addr));
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let lb = lb.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve_health(stream, lb).await {
+                        log_warn(format!("Failed to serve health request: {:?}", e));
+                    }
+                });
+            }
+            Err(e) => log_warn(format!("Failed to accept health connection: {:?}", e)),
+        }
+    }
+}
+
+async fn serve_health(mut stream: TcpStream, lb: Arc<LoadBalancer>) -> std::io::Result<()> {
+    let mut buf = [0u8; 1024];
+    // A client that connects and never sends anything would hold the task and socket forever
+    let Ok(len) = timeout(REQUEST_TIMEOUT, stream.read(&mut buf)).await else {
+        return Ok(());
+    };
+    let len = len?;
+    let request = String::from_utf8_lossy(&buf[..len]);
+
+    let response = if request.starts_with("GET /healthz ") {
+        let (status, body) = if lb.is_healthy().await {
+            ("200 OK", "HEALTHY\n")
+        } else {
+            ("503 Service Unavailable", "UNHEALTHY\n")
+        };
+        format!(
+            "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
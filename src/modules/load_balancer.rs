@@ -1,14 +1,41 @@
-use std::collections::HashMap;
-use std::net::SocketAddr;
-use tokio::sync::Mutex;
-use tokio::time::{sleep, Duration};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use rand::Rng;
+use chrono::Local;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::{watch, Mutex, Semaphore};
+use tokio::runtime::Handle;
+use tokio::task::JoinSet;
+use tokio::time::{sleep, timeout, Duration, Instant};
 use tokio::net::{TcpStream, UdpSocket};
-use crate::modules::utils::log;
+use crate::modules::hashing::{HashRing, MaglevTable};
+use crate::modules::health::{probe_http, probe_tcp, probe_udp, Circuit, HealthCheckConfig, HealthStreak, HealthTransitions, Latency, ProbeError};
+use crate::modules::metrics::Metrics;
+use crate::modules::rate_limit::ConnRateLimiter;
+#[cfg(feature = "grpc")]
+use crate::modules::grpc::probe_grpc;
+use crate::modules::logging::{log, log_debug, log_event, log_warn, LogLevel};
+use serde_json::json;
+use crate::modules::utils::{jittered, unspecified_addr_for};
+
+const DETECT_TIMEOUT: Duration = Duration::from_secs(1); // Upper bound for each protocol detection probe
+const SLOW_START_SCALE: i64 = 100; // Weight resolution, so even weight 1 can be ramped up gradually
+const OUTLIER_MIN_RESPONSES: usize = 10; // Responses within the window before a 5xx ratio can eject a backend
 
 #[derive(Debug, Clone, Copy)]
 pub enum LoadBalancerMode {
     RoundRobin,
     LeastConnections,
+    WeightedLeastConnections,
+    LeastResponseTime,
+    Random,
+    PowerOfTwoChoices,
+    ConsistentHash,
+    Adaptive,
+    Maglev,
 }
 
 impl std::str::FromStr for LoadBalancerMode {
@@ -18,12 +45,66 @@ impl std::str::FromStr for LoadBalancerMode {
         match input.to_lowercase().as_str() {
             "round-robin" => Ok(LoadBalancerMode::RoundRobin),
             "least-connections" => Ok(LoadBalancerMode::LeastConnections),
+            "weighted-least-connections" => Ok(LoadBalancerMode::WeightedLeastConnections),
+            "least-response-time" => Ok(LoadBalancerMode::LeastResponseTime),
+            "random" => Ok(LoadBalancerMode::Random),
+            "p2c" => Ok(LoadBalancerMode::PowerOfTwoChoices),
+            "consistent-hash" => Ok(LoadBalancerMode::ConsistentHash),
+            "adaptive" => Ok(LoadBalancerMode::Adaptive),
+            "maglev" => Ok(LoadBalancerMode::Maglev),
             _ => Err(()),
         }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+impl std::fmt::Display for LoadBalancerMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            LoadBalancerMode::RoundRobin => "round-robin",
+            LoadBalancerMode::LeastConnections => "least-connections",
+            LoadBalancerMode::WeightedLeastConnections => "weighted-least-connections",
+            LoadBalancerMode::LeastResponseTime => "least-response-time",
+            LoadBalancerMode::Random => "random",
+            LoadBalancerMode::PowerOfTwoChoices => "p2c",
+            LoadBalancerMode::ConsistentHash => "consistent-hash",
+            LoadBalancerMode::Adaptive => "adaptive",
+            LoadBalancerMode::Maglev => "maglev",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Whether round-robin rotates over all backends at once or over the groups first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RrScope {
+    Global,
+    Group,
+}
+
+impl std::str::FromStr for RrScope {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<RrScope, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "global" => Ok(RrScope::Global),
+            "group" => Ok(RrScope::Group),
+            _ => Err(()),
+        }
+    }
+}
+
+impl std::fmt::Display for RrScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            RrScope::Global => "global",
+            RrScope::Group => "group",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Protocol {
     TCP,
     UDP,
@@ -33,6 +114,14 @@ pub enum Protocol {
 pub struct Backend {
     pub addr: SocketAddr,
     pub protocol: Protocol,
+    pub weight: u32,  // Relative share of traffic, 0 excludes the backend from selection
+}
+
+impl Backend {
+    // The same address may be served once per protocol when listening on TCP and UDP
+    pub fn is_same(&self, other: &Backend) -> bool {
+        self.addr == other.addr && self.protocol == other.protocol
+    }
 }
 
 pub struct LoadBalancer {
@@ -40,200 +129,1455 @@ pub struct LoadBalancer {
     pub active_backends: Mutex<HashMap<String, Vec<Backend>>>,  // Active backends by hostname
     pub current: Mutex<HashMap<String, usize>>,  // Current index for each hostname group
     pub mode: LoadBalancerMode,
-    pub connection_counts: Mutex<HashMap<String, usize>>,  // Track connections by hostname group
+    pub connection_counts: Mutex<HashMap<SocketAddr, usize>>,  // Track live connections per backend address, shared by TCP and UDP
+    pub current_weights: Mutex<HashMap<(String, SocketAddr), i64>>,  // Smooth weighted round-robin state per selection scope and backend
+    pub hash_rings: Mutex<HashMap<(String, Protocol), HashRing>>,  // Consistent hash ring per scope and protocol, rebuilt when the active set changes
+    pub maglev_tables: Mutex<HashMap<(String, Protocol), MaglevTable>>,  // Maglev lookup table per scope and protocol, rebuilt like the hash rings
+    pub health: HealthCheckConfig,
+    pub health_streaks: Mutex<HashMap<(SocketAddr, Protocol), HealthStreak>>,  // Consecutive probe results per backend
+    pub transitions: Mutex<HashMap<(SocketAddr, Protocol), HealthTransitions>>,  // When each backend last became healthy or unhealthy
+    pub probe_errors: Mutex<HashMap<(SocketAddr, Protocol), ProbeError>>,  // Cause of each backend's last failed health check
+    pub health_sweeps: watch::Sender<u64>,  // Health check rounds completed since startup
+    pub wait_for_health: bool,  // Traffic waits for the first round, which then runs right away and does not wait for `fall`
+    pub strict_health: bool,  // New backends start inactive and join the rotation after `rise` passed health checks
+    pub draining: AtomicBool,  // Set once shutdown starts, no new connections are accepted
+    pub all_down: AtomicBool,  // Set while no backend is active, so the outage is logged once when it starts and once when it ends
+    pub admin_drained: Mutex<HashSet<SocketAddr>>,  // Taken out of rotation over UDS, health checks leave them alone
+    pub admin_added: Mutex<HashMap<String, Vec<Backend>>>,  // Added over UDS by group, kept when the configuration is reloaded
+    pub ring_groups: HashSet<String>,  // Groups resolved from DNS, their members are replaced on every refresh
+    pub drain_state: Option<PathBuf>,  // File admin_drained is saved to, so drains survive a restart
+    pub removing: Mutex<HashMap<(String, SocketAddr, Protocol), Instant>>,  // Group members out of rotation until their connections drained, and since when
+    pub drain_timeout: Duration,  // Longest wait for the connections of a removed backend
+    pub passive_strikes: Mutex<HashMap<(SocketAddr, Protocol), Vec<Instant>>>,  // Recent failed forwards per backend
+    pub circuits: Mutex<HashMap<SocketAddr, Circuit>>,  // Circuit breaker state per TCP backend, missing means closed
+    pub http_responses: Mutex<HashMap<SocketAddr, Vec<(Instant, bool)>>>,  // Recent HTTP responses per backend, true for a 5xx
+    pub outliers: Mutex<HashMap<SocketAddr, Instant>>,  // Backends ejected for their 5xx ratio, and since when
+    pub latencies: Mutex<HashMap<SocketAddr, Latency>>,  // Smoothed connect latency per backend, only kept for least-response-time and adaptive
+    pub backend_bytes: Mutex<HashMap<SocketAddr, (u64, u64)>>,  // Bytes forwarded to and from each backend since startup
+    pub metrics: Metrics,
+    pub max_conns: Option<usize>,  // Connection limit for backends without their own limit
+    pub backend_max_conns: HashMap<SocketAddr, usize>,  // Per-backend connection limits
+    pub group_tiers: HashMap<String, u32>,  // Failover tier per group, groups without one are tier 0
+    pub group_modes: HashMap<String, LoadBalancerMode>,  // Groups that pick their backends with another mode than `mode`
+    pub rr_scope: RrScope,  // Group scope gives every group an equal share of round-robin, whatever its size
+    pub global_conns: Option<Arc<Semaphore>>,  // One permit per TCP connection when max_global_conns is set
+    pub conn_rate: Option<ConnRateLimiter>,  // New TCP connections per second and client IP when conn_rate is set
+    pub slow_start: Option<Duration>,  // Time over which a newly healthy backend ramps up to its full weight
+    pub activated_at: Mutex<HashMap<SocketAddr, Instant>>,  // When backends in slow start became healthy
+    pub source_addr: Option<IpAddr>,  // Local IP that backend connections and health checks originate from
+    pub adaptive_latency_weight: f64,  // Share of the adaptive score taken by latency (0.0 to 1.0), the rest by connections
 }
 
 impl LoadBalancer {
-    pub fn new(mode: LoadBalancerMode) -> Self {
+    pub fn new(mode: LoadBalancerMode, health: HealthCheckConfig) -> Self {
         LoadBalancer {
             backends: Mutex::new(HashMap::new()),
             active_backends: Mutex::new(HashMap::new()),
             current: Mutex::new(HashMap::new()),
             mode,
             connection_counts: Mutex::new(HashMap::new()),
+            current_weights: Mutex::new(HashMap::new()),
+            hash_rings: Mutex::new(HashMap::new()),
+            maglev_tables: Mutex::new(HashMap::new()),
+            health,
+            health_streaks: Mutex::new(HashMap::new()),
+            transitions: Mutex::new(HashMap::new()),
+            probe_errors: Mutex::new(HashMap::new()),
+            health_sweeps: watch::Sender::new(0),
+            wait_for_health: false,
+            strict_health: false,
+            draining: AtomicBool::new(false),
+            all_down: AtomicBool::new(false),
+            admin_drained: Mutex::new(HashSet::new()),
+            admin_added: Mutex::new(HashMap::new()),
+            ring_groups: HashSet::new(),
+            drain_state: None,
+            removing: Mutex::new(HashMap::new()),
+            drain_timeout: Duration::from_secs(30),
+            passive_strikes: Mutex::new(HashMap::new()),
+            circuits: Mutex::new(HashMap::new()),
+            http_responses: Mutex::new(HashMap::new()),
+            outliers: Mutex::new(HashMap::new()),
+            latencies: Mutex::new(HashMap::new()),
+            backend_bytes: Mutex::new(HashMap::new()),
+            metrics: Metrics::default(),
+            max_conns: None,
+            backend_max_conns: HashMap::new(),
+            group_tiers: HashMap::new(),
+            group_modes: HashMap::new(),
+            rr_scope: RrScope::Global,
+            global_conns: None,
+            conn_rate: None,
+            slow_start: None,
+            activated_at: Mutex::new(HashMap::new()),
+            source_addr: None,
+            adaptive_latency_weight: 0.5,
         }
     }
 
-    pub async fn add_backends(&self, new_backends: HashMap<String, Vec<(SocketAddr, Option<Protocol>)>>) {
+    pub async fn add_backends(&self, new_backends: HashMap<String, Vec<(SocketAddr, Option<Protocol>, u32)>>) {
         let mut backends = self.backends.lock().await;
         let mut active_backends = self.active_backends.lock().await;
         let mut connection_counts = self.connection_counts.lock().await;
         let mut current = self.current.lock().await;
+        let admin_drained = self.admin_drained.lock().await;
 
         for (hostname, ips) in new_backends {
             let mut backend_list: Vec<Backend> = Vec::new();
 
-            for (addr, protocol) in ips {
+            for (addr, protocol, weight) in ips {
+                connection_counts.entry(addr).or_insert(0);
+
                 let determined_protocol = if let Some(p) = protocol {
                     p // Use the explicitly provided protocol if available
                 } else {
                     // Dynamically determine protocol (TCP or UDP)
-                    detect_protocol(addr).await.unwrap_or_else(|| Protocol::TCP)
+                    detect_protocol(addr).await.unwrap_or(Protocol::TCP)
                 };
 
                 backend_list.push(Backend {
                     addr,
                     protocol: determined_protocol,
+                    weight,
                 });
             }
 
-            // Insert into the backends and active_backends HashMaps
+            // Insert into the backends and active_backends HashMaps, strict health checking leaves the activation to the probes
             backends.insert(hostname.clone(), backend_list.clone());
-            active_backends.insert(hostname.clone(), backend_list.clone());
+            let active_list = if self.strict_health {
+                Vec::new()
+            } else {
+                backend_list.iter().filter(|b| !admin_drained.contains(&b.addr)).cloned().collect()
+            };
+            active_backends.insert(hostname.clone(), active_list);
 
-            // Initialize connection counts and round-robin index
-            connection_counts.entry(hostname.clone()).or_insert(0);
-            current.entry(hostname).or_insert(0); // Initialize round-robin index
+            // Initialize round-robin index
+            current.entry(hostname).or_insert(0);
         }
 
         log(format!("Added backends: {:?}", backends));
+        log_shared_backends(&backends);
     }
 
-    pub async fn next_backend(&self) -> Option<Backend> {
+    // Replace the members of a dynamically resolved group (e.g. a ring domain) in place.
+    // Backends that stay keep their active state, new ones start active like in add_backends (unless strict_health is set)
+    // and vanished ones leave the rotation right away but stay in the group until their
+    // connections drained (see reap_removed_backends). An empty result removes the whole group.
+    pub async fn update_dynamic_backends(&self, label: &str, resolved: Vec<(SocketAddr, Option<Protocol>, u32)>) {
+        let new_list: Vec<Backend> = resolved
+            .into_iter()
+            .map(|(addr, protocol, weight)| Backend {
+                addr,
+                protocol: protocol.unwrap_or(Protocol::TCP),
+                weight,
+            })
+            .collect();
+
+        let mut backends = self.backends.lock().await;
+        let mut active_backends = self.active_backends.lock().await;
+        let mut connection_counts = self.connection_counts.lock().await;
+        let mut current = self.current.lock().await;
+        let admin_drained = self.admin_drained.lock().await;
+        let mut removing = self.removing.lock().await;
+
+        // Members still draining from an earlier update no longer count as part of the group
+        let all_members = backends.get(label).cloned().unwrap_or_default();
+        let is_removing = |b: &Backend| removing.contains_key(&(label.to_string(), b.addr, b.protocol));
+        let old_list: Vec<Backend> = all_members.iter().filter(|b| !is_removing(b)).cloned().collect();
+        let added: Vec<Backend> = new_list.iter().filter(|b| !old_list.iter().any(|o| o.is_same(b))).cloned().collect();
+        let removed: Vec<Backend> = old_list.iter().filter(|o| !new_list.iter().any(|b| b.is_same(o))).cloned().collect();
+        let reweighted = new_list.iter().any(|b| old_list.iter().any(|o| o.is_same(b) && o.weight != b.weight));
+
+        if added.is_empty() && removed.is_empty() && !reweighted {
+            return;
+        }
+
+        // Keep the active state of surviving backends, activate the new ones
+        let previously_active = active_backends.remove(label).unwrap_or_default();
+        let active_list: Vec<Backend> = new_list
+            .iter()
+            .filter(|b| previously_active.iter().any(|a| a.is_same(b)) || (!self.strict_health && added.iter().any(|a| a.is_same(b))))
+            .filter(|b| !admin_drained.contains(&b.addr))
+            .cloned()
+            .collect();
+
+        // A backend that comes back while draining simply stays
+        let mut members = new_list.clone();
+        members.extend(all_members.iter().filter(|b| is_removing(b) && !new_list.iter().any(|n| n.is_same(b))));
+        for backend in &new_list {
+            removing.remove(&(label.to_string(), backend.addr, backend.protocol));
+        }
+        for backend in &removed {
+            removing.insert((label.to_string(), backend.addr, backend.protocol), Instant::now());
+            members.push(*backend);
+        }
+
+        for backend in &added {
+            connection_counts.entry(backend.addr).or_insert(0);
+        }
+        current.entry(label.to_string()).or_insert(0);
+        backends.insert(label.to_string(), members);
+        active_backends.insert(label.to_string(), active_list);
+        if new_list.is_empty() {
+            log(format!("Removed all backends of group {}, waiting for their connections to drain", label));
+        }
+
+        for backend in &added {
+            log_event(
+                LogLevel::Info,
+                "backend_added",
+                format!("Group {}: added backend {} ({:?})", label, backend.addr, backend.protocol),
+                &[("group", json!(label)), ("backend", json!(backend.addr.to_string())), ("protocol", json!(format!("{:?}", backend.protocol)))],
+            );
+        }
+        for backend in &new_list {
+            if let Some(old) = old_list.iter().find(|o| o.is_same(backend) && o.weight != backend.weight) {
+                log_event(
+                    LogLevel::Info,
+                    "backend_reweighted",
+                    format!("Group {}: weight of backend {} ({:?}) changed from {} to {}", label, backend.addr, backend.protocol, old.weight, backend.weight),
+                    &[("group", json!(label)), ("backend", json!(backend.addr.to_string())), ("weight", json!(backend.weight))],
+                );
+            }
+        }
+        for backend in &removed {
+            log_event(
+                LogLevel::Info,
+                "backend_removed",
+                format!("Group {}: removed backend {} ({:?})", label, backend.addr, backend.protocol),
+                &[("group", json!(label)), ("backend", json!(backend.addr.to_string())), ("protocol", json!(format!("{:?}", backend.protocol)))],
+            );
+        }
+        if !added.is_empty() {
+            log_shared_backends(&backends);
+        }
+        self.track_all_down(&active_backends);
+    }
+
+    // A balancer without any active backend fails every connection, log that as an error when it
+    // happens and when a backend is back. Until the first health round the active set only mirrors
+    // the configuration, so nothing is reported before.
+    fn track_all_down(&self, active_backends: &HashMap<String, Vec<Backend>>) {
+        if *self.health_sweeps.borrow() == 0 {
+            return;
+        }
+        let active: HashSet<(SocketAddr, Protocol)> = active_backends.values().flatten().map(|b| (b.addr, b.protocol)).collect();
+        let all_down = active.is_empty();
+        if self.all_down.swap(all_down, Ordering::SeqCst) == all_down {
+            return;
+        }
+        if all_down {
+            log_event(LogLevel::Error, "all_backends_down", "No backend is active, connections cannot be forwarded.".to_string(), &[]);
+        } else {
+            log_event(
+                LogLevel::Error,
+                "backends_restored",
+                format!("{} backend(s) active again after all were down.", active.len()),
+                &[("active", json!(active.len()))],
+            );
+        }
+    }
+
+    // Connection limit of a backend, its own limit wins over the global one
+    fn connection_limit(&self, addr: &SocketAddr) -> Option<usize> {
+        self.backend_max_conns.get(addr).copied().or(self.max_conns)
+    }
+
+    // Active backends that can take another connection. The count is checked at selection time,
+    // so concurrent selections may overshoot a limit by the number of in-flight selections.
+    pub async fn selectable_backends(&self, protocol: Protocol) -> Vec<Backend> {
+        let mut all_backends = self.active_backends_for(protocol).await;
+        if self.health.circuit_failures > 0 {
+            let circuits = self.circuits.lock().await;
+            all_backends.retain(|b| self.circuit_allows(circuits.get(&b.addr)));
+        }
+        self.below_connection_limit(all_backends).await
+    }
+
+    // Closed circuits and open ones whose cooldown passed (for a trial) can be selected
+    fn circuit_allows(&self, circuit: Option<&Circuit>) -> bool {
+        match circuit {
+            None | Some(Circuit::Closed { .. }) => true,
+            Some(Circuit::Open { since }) => since.elapsed() >= self.health.circuit_cooldown,
+            Some(Circuit::HalfOpen) => false,
+        }
+    }
+
+    async fn below_connection_limit(&self, mut all_backends: Vec<Backend>) -> Vec<Backend> {
+        if self.max_conns.is_some() || !self.backend_max_conns.is_empty() {
+            let connection_counts = self.connection_counts.lock().await;
+            all_backends.retain(|b| match self.connection_limit(&b.addr) {
+                Some(limit) => connection_counts.get(&b.addr).copied().unwrap_or(0) < limit,
+                None => true,
+            });
+        }
+        all_backends
+    }
+
+    // Whether backends for the protocol are active but every one of them is at its connection limit
+    pub async fn is_saturated(&self, protocol: Protocol) -> bool {
+        let all_backends = self.active_backends_for(protocol).await;
+        !all_backends.is_empty() && self.below_connection_limit(all_backends).await.is_empty()
+    }
+
+    // Flatten all IP addresses from all hostnames into a single list, skipping zero-weight backends
+    // and backends of another protocol. Only groups of the lowest tier that has an active backend
+    // take part, higher tiers are backups. Groups are ordered by label and backends by address so
+    // the sequence is stable between calls. An address listed in several groups appears once, as
+    // the entry of its owner: the first of those groups (of the lowest tier) by label.
+    async fn active_backends_for(&self, protocol: Protocol) -> Vec<Backend> {
         let active_backends = self.active_backends.lock().await;
+        let mut groups: Vec<(u32, &String, Vec<Backend>)> = active_backends
+            .iter()
+            .map(|(label, backends)| {
+                let tier = self.group_tiers.get(label).copied().unwrap_or(0);
+                let mut group: Vec<Backend> = backends
+                    .iter()
+                    .filter(|b| b.weight > 0 && b.protocol == protocol)
+                    .cloned()
+                    .collect();
+                group.sort_by_key(|b| b.addr);
+                (tier, label, group)
+            })
+            .filter(|(_, _, group)| !group.is_empty())
+            .collect();
+        groups.sort_by(|a, b| a.1.cmp(b.1));
 
-        // Flatten all IP addresses from all hostnames into a single list
-        let all_backends: Vec<Backend> = active_backends.values().flatten().cloned().collect();
+        let Some(lowest_tier) = groups.iter().map(|(tier, _, _)| *tier).min() else {
+            return Vec::new();
+        };
+        let mut seen = HashSet::new();
+        groups
+            .into_iter()
+            .filter(|(tier, _, _)| *tier == lowest_tier)
+            .flat_map(|(_, _, group)| group)
+            .filter(|b| seen.insert((b.addr, b.protocol)))
+            .collect()
+    }
+
+    // Selectable backends without the excluded addresses. Only warns when nothing is active at all,
+    // running out of candidates after exclusions is up to the caller.
+    async fn candidates(&self, protocol: Protocol, excluded: &[SocketAddr]) -> Option<Vec<Backend>> {
+        let mut all_backends = self.selectable_backends(protocol).await;
+
+        if all_backends.is_empty() {
+            if !self.is_saturated(protocol).await {
+                log_warn("No active backends available.".to_string());
+            }
+            return None;
+        }
 
+        all_backends.retain(|b| !excluded.contains(&b.addr));
         if all_backends.is_empty() {
-            log("No active backends available.".to_string());
             return None;
         }
+        Some(all_backends)
+    }
+
+    // Select a backend for a specific client, skipping `excluded` (e.g. backends that already
+    // refused this connection). Modes keyed on the client address use it, every other mode
+    // only looks at the candidates.
+    pub async fn next_backend_for_client(&self, ip: IpAddr, protocol: Protocol, excluded: &[SocketAddr]) -> Option<Backend> {
+        let all_backends = self.candidates(protocol, excluded).await?;
+        if self.rr_scope == RrScope::Group && matches!(self.mode, LoadBalancerMode::RoundRobin) {
+            return self.select_group_first(ip, protocol, all_backends, !excluded.is_empty()).await;
+        }
+
+        let backend = self.select_for_client(self.mode, "", ip, protocol, all_backends.clone(), !excluded.is_empty()).await?;
+        if self.group_modes.is_empty() {
+            return Some(backend);
+        }
+
+        // The global mode picked the group, a group with a mode of its own then picks the backend.
+        // A backend shared by several groups belongs to the first of them by tier and label, as in active_backends_for.
+        let overridden = {
+            let active_backends = self.active_backends.lock().await;
+            let mut groups: Vec<(&String, &Vec<Backend>)> = active_backends.iter().collect();
+            groups.sort_by_key(|(label, _)| (self.group_tiers.get(*label).copied().unwrap_or(0), *label));
+            groups
+                .into_iter()
+                .find(|(_, members)| members.iter().any(|b| b.is_same(&backend)))
+                .and_then(|(label, members)| Some((label, members, *self.group_modes.get(label)?)))
+                .map(|(label, members, mode)| {
+                    let group: Vec<Backend> = all_backends.iter().filter(|b| members.iter().any(|m| m.is_same(b))).cloned().collect();
+                    (label.clone(), group, mode)
+                })
+        };
+        match overridden {
+            Some((label, group, mode)) => self.select_for_client(mode, &label, ip, protocol, group, !excluded.is_empty()).await,
+            None => Some(backend),
+        }
+    }
+
+    // rr_scope=group: rotate over the groups with a candidate, then pick within the group with its own
+    // round-robin (or its group_mode), so a group of one backend gets as much traffic as a group of ten.
+    async fn select_group_first(&self, ip: IpAddr, protocol: Protocol, all_backends: Vec<Backend>, retry: bool) -> Option<Backend> {
+        let groups: Vec<(String, Vec<Backend>)> = {
+            let active_backends = self.active_backends.lock().await;
+            let mut labels: Vec<&String> = active_backends.keys().collect();
+            labels.sort_by_key(|label| (self.group_tiers.get(*label).copied().unwrap_or(0), *label));
+            // A backend shared by several groups is a member of the first of them only
+            let mut assigned = HashSet::new();
+            labels
+                .into_iter()
+                .filter_map(|label| {
+                    let members: Vec<Backend> = all_backends
+                        .iter()
+                        .filter(|b| active_backends[label].iter().any(|m| m.is_same(b)) && assigned.insert((b.addr, b.protocol)))
+                        .cloned()
+                        .collect();
+                    (!members.is_empty()).then(|| (label.clone(), members))
+                })
+                .collect()
+        };
+        if groups.is_empty() {
+            return None;
+        }
+
+        let index = {
+            let mut current = self.current.lock().await;
+            let next = current.entry("group_round_robin".to_string()).or_insert(0);
+            let index = *next % groups.len();
+            *next = index + 1;
+            index
+        };
+        let (label, members) = groups.into_iter().nth(index)?;
+        let mode = self.group_modes.get(&label).copied().unwrap_or(LoadBalancerMode::RoundRobin);
+        self.select_for_client(mode, &label, ip, protocol, members, retry).await
+    }
+
+    // Apply `mode` to the candidates of one selection scope ("" for the whole balancer, a group label
+    // for a group with its own mode). A retry hashes onto the remaining backends without replacing
+    // the cached ring or table.
+    async fn select_for_client(
+        &self,
+        mode: LoadBalancerMode,
+        scope: &str,
+        ip: IpAddr,
+        protocol: Protocol,
+        all_backends: Vec<Backend>,
+        retry: bool,
+    ) -> Option<Backend> {
+        let key = (scope.to_string(), protocol);
+        match mode {
+            LoadBalancerMode::ConsistentHash => {
+                if retry {
+                    return HashRing::new(&all_backends).get(ip);
+                }
+
+                let mut hash_rings = self.hash_rings.lock().await;
+                if !hash_rings.get(&key).is_some_and(|ring| ring.matches(&all_backends)) {
+                    hash_rings.insert(key.clone(), HashRing::new(&all_backends));
+                }
+                hash_rings.get(&key)?.get(ip)
+            },
+            LoadBalancerMode::Maglev => {
+                if retry {
+                    return MaglevTable::new(&all_backends).get(ip);
+                }
+
+                let mut maglev_tables = self.maglev_tables.lock().await;
+                if !maglev_tables.get(&key).is_some_and(|table| table.matches(&all_backends)) {
+                    maglev_tables.insert(key.clone(), MaglevTable::new(&all_backends));
+                }
+                maglev_tables.get(&key)?.get(ip)
+            },
+            _ => self.select(mode, scope, all_backends).await,
+        }
+    }
+
+    // Label of the group serving a host name, matched against the group label with or without its port
+    pub async fn find_group(&self, host: &str) -> Option<String> {
+        let backends = self.backends.lock().await;
+        backends
+            .keys()
+            .find(|label| {
+                let label_host = match label.rsplit_once(':') {
+                    Some((name, port)) if port.parse::<u16>().is_ok() => name,
+                    _ => label.as_str(),
+                };
+                label_host.trim_end_matches('.').eq_ignore_ascii_case(host)
+            })
+            .cloned()
+    }
+
+    // Select a backend from a single group, used when the traffic itself names its destination
+    pub async fn next_backend_in_group(&self, label: &str, protocol: Protocol, excluded: &[SocketAddr]) -> Option<Backend> {
+        let mut group: Vec<Backend> = {
+            let active_backends = self.active_backends.lock().await;
+            let mut group: Vec<Backend> = active_backends
+                .get(label)
+                .map(|backends| {
+                    backends
+                        .iter()
+                        .filter(|b| b.weight > 0 && b.protocol == protocol && !excluded.contains(&b.addr))
+                        .cloned()
+                        .collect()
+                })
+                .unwrap_or_default();
+            group.sort_by_key(|b| b.addr);
+            group
+        };
+        if self.health.circuit_failures > 0 {
+            let circuits = self.circuits.lock().await;
+            group.retain(|b| self.circuit_allows(circuits.get(&b.addr)));
+        }
+        let group = self.below_connection_limit(group).await;
+
+        if group.is_empty() {
+            return None;
+        }
+        self.select(self.group_modes.get(label).copied().unwrap_or(self.mode), label, group).await
+    }
+
+    // Weight in units of 1/SLOW_START_SCALE, rising linearly from almost zero to the full weight
+    // during the slow-start window after the backend became healthy
+    fn ramped_weight(&self, backend: &Backend, activated_at: &HashMap<SocketAddr, Instant>) -> i64 {
+        let full = backend.weight as i64 * SLOW_START_SCALE;
+        match (self.slow_start, activated_at.get(&backend.addr)) {
+            (Some(window), Some(at)) if at.elapsed() < window => {
+                let ramp = at.elapsed().as_secs_f64() / window.as_secs_f64();
+                ((full as f64 * ramp) as i64).max(1)
+            }
+            _ => full,
+        }
+    }
 
-        match self.mode {
+    // Apply a balancing mode to a non-empty candidate list. Selection state is kept per `scope`,
+    // so a group with its own mode does not disturb the rotation across all groups.
+    async fn select(&self, mode: LoadBalancerMode, scope: &str, all_backends: Vec<Backend>) -> Option<Backend> {
+        // Tie-break offsets of the whole balancer keep their plain names
+        let offset_key = |name: &str| if scope.is_empty() { name.to_string() } else { format!("{}@{}", name, scope) };
+
+        match mode {
             LoadBalancerMode::RoundRobin => {
-                let mut current = self.current.lock().await;
+                let mut current_weights = self.current_weights.lock().await;
+                let activated_at = self.activated_at.lock().await;
+
+                // Forget the state of backends that left the active set so they rejoin fresh
+                current_weights.retain(|(entry_scope, addr), _| entry_scope != scope || all_backends.iter().any(|b| b.addr == *addr));
 
-                // Ensure there is an entry for round-robin index
-                let idx = current.entry("global".to_string()).or_insert(0);
-                let backend = all_backends.get(*idx)?.clone();  // Clone the Backend struct
+                // Smooth weighted round-robin: every backend gains its weight, the highest
+                // current weight wins and is lowered by the total weight of all candidates.
+                // With equal weights this is plain round-robin.
+                let weights: Vec<i64> = all_backends.iter().map(|b| self.ramped_weight(b, &activated_at)).collect();
+                let total_weight: i64 = weights.iter().sum();
+                let mut selected: Option<Backend> = None;
+                let mut selected_weight = i64::MIN;
 
-                // Advance to the next IP in the list, wrapping around
-                *idx = (*idx + 1) % all_backends.len();
-                Some(backend)  // Return the cloned backend
+                for (backend, weight) in all_backends.iter().zip(&weights) {
+                    let current_weight = current_weights.entry((scope.to_string(), backend.addr)).or_insert(0);
+                    *current_weight += weight;
+                    if *current_weight > selected_weight {
+                        selected_weight = *current_weight;
+                        selected = Some(*backend);
+                    }
+                }
+
+                let backend = selected?;
+                if let Some(current_weight) = current_weights.get_mut(&(scope.to_string(), backend.addr)) {
+                    *current_weight -= total_weight;
+                }
+                Some(backend)
             },
             LoadBalancerMode::LeastConnections => {
                 let connection_counts = self.connection_counts.lock().await;
+                let mut current = self.current.lock().await;
+
+                // Start scanning at a rotating offset so ties are broken round-robin
+                let offset = current.entry(offset_key("least_connections")).or_insert(0);
+                let start = *offset % all_backends.len();
 
-                // Find the backend with the least connections
                 let mut least_connected = None;
                 let mut least_connections = usize::MAX;
 
-                for (hostname, backends) in active_backends.iter() {
-                    for backend in backends {
-                        if let Some(&count) = connection_counts.get(hostname) {
-                            if count < least_connections {
-                                least_connections = count;
-                                least_connected = Some(*backend);
-                            }
-                        }
+                for i in 0..all_backends.len() {
+                    let pos = (start + i) % all_backends.len();
+                    let count = connection_counts.get(&all_backends[pos].addr).copied().unwrap_or(0);
+                    if count < least_connections {
+                        least_connections = count;
+                        least_connected = Some(pos);
                     }
                 }
 
-                least_connected
+                let pos = least_connected?;
+                *offset = (pos + 1) % all_backends.len();
+                Some(all_backends[pos])
+            },
+            LoadBalancerMode::WeightedLeastConnections => {
+                let connection_counts = self.connection_counts.lock().await;
+                let activated_at = self.activated_at.lock().await;
+                let mut current = self.current.lock().await;
+
+                // Same rotating tie-break as least-connections
+                let offset = current.entry(offset_key("weighted_least_connections")).or_insert(0);
+                let start = *offset % all_backends.len();
+
+                // Minimize connections / weight, compared as cross products to stay in integers.
+                // Zero-weight backends never get here, with equal weights this is least-connections.
+                let mut least_loaded: Option<(usize, u64, u64)> = None;
+
+                for i in 0..all_backends.len() {
+                    let pos = (start + i) % all_backends.len();
+                    let count = connection_counts.get(&all_backends[pos].addr).copied().unwrap_or(0) as u64;
+                    let weight = self.ramped_weight(&all_backends[pos], &activated_at) as u64;
+                    let is_less = match least_loaded {
+                        Some((_, least_count, least_weight)) => count * least_weight < least_count * weight,
+                        None => true,
+                    };
+                    if is_less {
+                        least_loaded = Some((pos, count, weight));
+                    }
+                }
+
+                let (pos, _, _) = least_loaded?;
+                *offset = (pos + 1) % all_backends.len();
+                Some(all_backends[pos])
+            },
+            LoadBalancerMode::LeastResponseTime => {
+                let latencies = self.latencies.lock().await;
+                let mut current = self.current.lock().await;
+
+                // Same rotating tie-break as least-connections, backends without a measurement count as fastest
+                let offset = current.entry(offset_key("least_response_time")).or_insert(0);
+                let start = *offset % all_backends.len();
+
+                let mut fastest = None;
+                let mut fastest_millis = f64::MAX;
+
+                for i in 0..all_backends.len() {
+                    let pos = (start + i) % all_backends.len();
+                    let millis = latencies.get(&all_backends[pos].addr).map_or(0.0, Latency::millis);
+                    if millis < fastest_millis {
+                        fastest_millis = millis;
+                        fastest = Some(pos);
+                    }
+                }
+
+                let pos = fastest?;
+                *offset = (pos + 1) % all_backends.len();
+                Some(all_backends[pos])
+            },
+            LoadBalancerMode::Adaptive => {
+                let connection_counts = self.connection_counts.lock().await;
+                let latencies = self.latencies.lock().await;
+                let mut current = self.current.lock().await;
+
+                // Both factors are scaled to 0..1 relative to the busiest and slowest candidate, so neither
+                // dominates just because of its unit. Unmeasured backends count as fastest like in least-response-time.
+                let loads: Vec<(f64, f64)> = all_backends
+                    .iter()
+                    .map(|b| {
+                        let count = connection_counts.get(&b.addr).copied().unwrap_or(0) as f64;
+                        let millis = latencies.get(&b.addr).map_or(0.0, Latency::millis);
+                        (count, millis)
+                    })
+                    .collect();
+                let max_count = loads.iter().map(|(count, _)| *count).fold(0.0, f64::max);
+                let max_millis = loads.iter().map(|(_, millis)| *millis).fold(0.0, f64::max);
+                let scaled = |value: f64, max: f64| if max > 0.0 { value / max } else { 0.0 };
+
+                // Same rotating tie-break as least-connections
+                let offset = current.entry(offset_key("adaptive")).or_insert(0);
+                let start = *offset % all_backends.len();
+
+                let mut best = None;
+                let mut best_score = f64::MAX;
+
+                for i in 0..all_backends.len() {
+                    let pos = (start + i) % all_backends.len();
+                    let (count, millis) = loads[pos];
+                    let score = self.adaptive_latency_weight * scaled(millis, max_millis)
+                        + (1.0 - self.adaptive_latency_weight) * scaled(count, max_count);
+                    if score < best_score {
+                        best_score = score;
+                        best = Some(pos);
+                    }
+                }
+
+                let pos = best?;
+                *offset = (pos + 1) % all_backends.len();
+                Some(all_backends[pos])
+            },
+            LoadBalancerMode::Random => {
+                let idx = rand::thread_rng().gen_range(0..all_backends.len());
+                Some(all_backends[idx])
+            },
+            LoadBalancerMode::PowerOfTwoChoices => {
+                // With a single active backend there is nothing to choose from
+                if all_backends.len() < 2 {
+                    return Some(all_backends[0]);
+                }
+
+                // Sample two distinct backends and keep the one with fewer live connections
+                let (first, second) = {
+                    let mut rng = rand::thread_rng();
+                    let first = rng.gen_range(0..all_backends.len());
+                    let second = rng.gen_range(0..all_backends.len() - 1);
+                    (first, if second >= first { second + 1 } else { second })
+                };
+
+                let connection_counts = self.connection_counts.lock().await;
+                let first_count = connection_counts.get(&all_backends[first].addr).copied().unwrap_or(0);
+                let second_count = connection_counts.get(&all_backends[second].addr).copied().unwrap_or(0);
+
+                if second_count < first_count {
+                    Some(all_backends[second])
+                } else {
+                    Some(all_backends[first])
+                }
+            },
+            LoadBalancerMode::ConsistentHash | LoadBalancerMode::Maglev => {
+                // Without a client address there is no key to hash, spread randomly instead
+                let idx = rand::thread_rng().gen_range(0..all_backends.len());
+                Some(all_backends[idx])
             },
         }
     }
 
-    pub async fn increment_connection(&self, backend: Backend) {
-        let mut connection_counts = self.connection_counts.lock().await;
-        for (hostname, ips) in self.backends.lock().await.iter() {
-            if ips.iter().any(|b| b.addr == backend.addr) {
-                *connection_counts.entry(hostname.clone()).or_insert(0) += 1;
-                break;
+    // The balancer is healthy as long as at least one backend can receive traffic
+    pub async fn is_healthy(&self) -> bool {
+        let active_backends = self.active_backends.lock().await;
+        active_backends.values().any(|backends| !backends.is_empty())
+    }
+
+    // Wait until a health check round has completed and left at least one backend active
+    pub async fn wait_until_ready(&self) {
+        let mut sweeps = self.health_sweeps.subscribe();
+        loop {
+            if *sweeps.borrow_and_update() > 0 && self.is_healthy().await {
+                return;
+            }
+            if sweeps.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    pub async fn is_active(&self, backend: &Backend) -> bool {
+        let active_backends = self.active_backends.lock().await;
+        active_backends.values().flatten().any(|b| b.is_same(backend))
+    }
+
+    // Feed a connect or probe latency into the backend's moving average
+    pub async fn record_latency(&self, addr: SocketAddr, sample: Duration) {
+        let uses_latency = |mode: &LoadBalancerMode| matches!(mode, LoadBalancerMode::LeastResponseTime | LoadBalancerMode::Adaptive);
+        if !uses_latency(&self.mode) && !self.group_modes.values().any(uses_latency) {
+            return;
+        }
+        let mut latencies = self.latencies.lock().await;
+        match latencies.get_mut(&addr) {
+            Some(latency) => latency.record(sample),
+            None => {
+                latencies.insert(addr, Latency::new(sample));
             }
         }
     }
 
-    pub async fn decrement_connection(&self, backend: Backend) {
+    // Add forwarded bytes to the backend's running totals
+    pub async fn add_backend_bytes(&self, addr: SocketAddr, up: u64, down: u64) {
+        let mut backend_bytes = self.backend_bytes.lock().await;
+        let totals = backend_bytes.entry(addr).or_insert((0, 0));
+        totals.0 += up;
+        totals.1 += down;
+    }
+
+    // Count a connection to the backend until the returned guard is dropped
+    pub async fn track_connection(self: &Arc<Self>, backend: Backend) -> ConnectionGuard {
         let mut connection_counts = self.connection_counts.lock().await;
-        for (hostname, ips) in self.backends.lock().await.iter() {
-            if ips.iter().any(|b| b.addr == backend.addr) {
-                if let Some(count) = connection_counts.get_mut(hostname) {
-                    if *count > 0 {
-                        *count -= 1;
-                    }
+        *connection_counts.entry(backend.addr).or_insert(0) += 1;
+        ConnectionGuard { lb: self.clone(), addr: backend.addr }
+    }
+
+    // Add a backend to a group at runtime. It is not active until the health checks pass it,
+    // returns the resulting group members.
+    pub async fn add_backend(&self, label: &str, addr: SocketAddr, protocol: Option<Protocol>) -> Result<Vec<Backend>, String> {
+        // The next DNS refresh would drop it again without a word
+        if self.ring_groups.contains(label) {
+            return Err(format!("group {} is resolved from DNS, its members are replaced on every refresh", label));
+        }
+        // Detect before taking the locks, probing may take a while
+        let protocol = match protocol {
+            Some(protocol) => protocol,
+            None => detect_protocol(addr).await.unwrap_or(Protocol::TCP),
+        };
+        let backend = Backend { addr, protocol, weight: 1 };
+
+        let mut backends = self.backends.lock().await;
+        let mut connection_counts = self.connection_counts.lock().await;
+        let mut current = self.current.lock().await;
+
+        let group = backends.entry(label.to_string()).or_default();
+        if group.iter().any(|b| b.is_same(&backend)) {
+            return Err(format!("backend {} ({:?}) already in group {}", addr, protocol, label));
+        }
+        group.push(backend);
+        connection_counts.entry(addr).or_insert(0);
+        current.entry(label.to_string()).or_insert(0);
+        self.admin_added.lock().await.entry(label.to_string()).or_default().push(backend);
+
+        log(format!("Group {}: added backend {} ({:?}) by admin command, waiting for health checks", label, addr, protocol));
+        Ok(group.clone())
+    }
+
+    // Backends added to `label` by admin command that are still in the group, so a reload can keep them.
+    // Entries removed since then are forgotten.
+    pub async fn admin_added_to(&self, label: &str) -> Vec<Backend> {
+        let backends = self.backends.lock().await;
+        let mut admin_added = self.admin_added.lock().await;
+        let group = backends.get(label).map_or(&[][..], Vec::as_slice);
+        let Some(added) = admin_added.get_mut(label) else {
+            return Vec::new();
+        };
+        added.retain(|backend| group.iter().any(|b| b.is_same(backend)));
+        let added = added.clone();
+        if added.is_empty() {
+            admin_added.remove(label);
+        }
+        added
+    }
+
+    // Remove every entry of an address from all groups, groups left empty are dropped.
+    // Returns the remaining members of each group the address was removed from.
+    pub async fn remove_backend(&self, addr: SocketAddr) -> Result<Vec<(String, Vec<Backend>)>, String> {
+        let mut backends = self.backends.lock().await;
+        let mut active_backends = self.active_backends.lock().await;
+
+        let mut changed = Vec::new();
+        for (label, group) in backends.iter_mut() {
+            if group.iter().any(|b| b.addr == addr) {
+                group.retain(|b| b.addr != addr);
+                changed.push((label.clone(), group.clone()));
+            }
+        }
+        if changed.is_empty() {
+            return Err(format!("unknown backend {}", addr));
+        }
+
+        for active_ips in active_backends.values_mut() {
+            active_ips.retain(|b| b.addr != addr);
+        }
+        backends.retain(|_, group| !group.is_empty());
+        active_backends.retain(|label, _| backends.contains_key(label));
+        self.track_all_down(&active_backends);
+
+        log(format!("Backend {} removed by admin command", addr));
+        Ok(changed)
+    }
+
+    // Take every entry of an address out of rotation until undrain_backend is called.
+    // Returns an error if the address is not configured.
+    pub async fn drain_backend(&self, addr: SocketAddr) -> Result<(), String> {
+        let backends = self.backends.lock().await;
+        if !backends.values().flatten().any(|b| b.addr == addr) {
+            return Err(format!("unknown backend {}", addr));
+        }
+        let mut active_backends = self.active_backends.lock().await;
+        let mut admin_drained = self.admin_drained.lock().await;
+
+        admin_drained.insert(addr);
+        self.save_drain_state(&admin_drained);
+        for active_ips in active_backends.values_mut() {
+            active_ips.retain(|b| b.addr != addr);
+        }
+        self.track_all_down(&active_backends);
+        log(format!("Backend {} drained by admin command", addr));
+        Ok(())
+    }
+
+    // Write the drained addresses to the drain_state file, through a temporary file so a crash
+    // never leaves a half-written one behind
+    fn save_drain_state(&self, admin_drained: &HashSet<SocketAddr>) {
+        let Some(path) = &self.drain_state else {
+            return;
+        };
+        let mut addrs: Vec<String> = admin_drained.iter().map(|addr| addr.to_string()).collect();
+        addrs.sort();
+        let mut contents = addrs.join("\n");
+        contents.push('\n');
+
+        let temp = path.with_extension("tmp");
+        if let Err(e) = std::fs::write(&temp, contents).and_then(|_| std::fs::rename(&temp, path)) {
+            log_warn(format!("Failed to save drained backends to {}: {:?}", path.display(), e));
+        }
+    }
+
+    // Take every entry of an address out of rotation and remove it from its groups once its
+    // connections finished or drain_timeout passed. Until then undrain_backend cancels the removal.
+    pub async fn drain_and_remove_backend(&self, addr: SocketAddr) -> Result<(), String> {
+        let backends = self.backends.lock().await;
+        let mut active_backends = self.active_backends.lock().await;
+        let mut removing = self.removing.lock().await;
+
+        let mut found = false;
+        for (label, group) in backends.iter() {
+            for backend in group.iter().filter(|b| b.addr == addr) {
+                removing.entry((label.clone(), backend.addr, backend.protocol)).or_insert_with(Instant::now);
+                found = true;
+            }
+        }
+        if !found {
+            return Err(format!("unknown backend {}", addr));
+        }
+        for active_ips in active_backends.values_mut() {
+            active_ips.retain(|b| b.addr != addr);
+        }
+        self.track_all_down(&active_backends);
+        log(format!("Backend {} drained by admin command, removing it once its connections finished", addr));
+        Ok(())
+    }
+
+    // Allow a drained address back, it rejoins once the health checks see it healthy again
+    pub async fn undrain_backend(&self, addr: SocketAddr) -> Result<(), String> {
+        let was_drained = {
+            let mut admin_drained = self.admin_drained.lock().await;
+            let was_drained = admin_drained.remove(&addr);
+            if was_drained {
+                self.save_drain_state(&admin_drained);
+            }
+            was_drained
+        };
+        let mut removing = self.removing.lock().await;
+        let was_removing = removing.keys().any(|(_, removing_addr, _)| *removing_addr == addr);
+        removing.retain(|(_, removing_addr, _), _| *removing_addr != addr);
+        if !was_drained && !was_removing {
+            return Err(format!("backend {} is not drained", addr));
+        }
+        log(format!("Backend {} undrained by admin command, waiting for health checks", addr));
+        Ok(())
+    }
+
+    // Remove draining group members once they have no connections left or drain_timeout passed,
+    // then forget the per-backend state of addresses that are no longer in any group
+    pub async fn reap_removed_backends(&self) {
+        loop {
+            sleep(Duration::from_secs(1)).await;
+
+            let connection_counts = self.connection_counts.lock().await.clone();
+            let mut backends = self.backends.lock().await;
+            let mut active_backends = self.active_backends.lock().await;
+            let mut removing = self.removing.lock().await;
+
+            let done: Vec<((String, SocketAddr, Protocol), bool)> = removing
+                .iter()
+                .filter_map(|(key, since)| {
+                    let drained = connection_counts.get(&key.1).copied().unwrap_or(0) == 0;
+                    (drained || since.elapsed() >= self.drain_timeout).then(|| (key.clone(), drained))
+                })
+                .collect();
+            if done.is_empty() {
+                continue;
+            }
+
+            for ((label, addr, protocol), drained) in done {
+                removing.remove(&(label.clone(), addr, protocol));
+                let Some(group) = backends.get_mut(&label) else {
+                    continue;
+                };
+                group.retain(|b| !(b.addr == addr && b.protocol == protocol));
+                if group.is_empty() {
+                    backends.remove(&label);
+                    active_backends.remove(&label);
                 }
-                break;
+                let message = if drained {
+                    format!("Group {}: backend {} ({:?}) drained and removed", label, addr, protocol)
+                } else {
+                    format!("Group {}: backend {} ({:?}) removed after drain_timeout with connections still open", label, addr, protocol)
+                };
+                log_event(
+                    LogLevel::Info,
+                    "backend_drained",
+                    message,
+                    &[("group", json!(label)), ("backend", json!(addr.to_string())), ("protocol", json!(format!("{:?}", protocol)))],
+                );
+            }
+
+            let configured: HashSet<SocketAddr> = backends.values().flatten().map(|b| b.addr).collect();
+            drop((backends, active_backends, removing));
+            self.forget_removed_backends(&configured).await;
+        }
+    }
+
+    // Drop per-backend state of addresses outside `configured`. A connection count that is still
+    // in use is kept, the guards of connections that outlived drain_timeout still release it.
+    // Admin drains are kept too, an address that rejoins later stays drained until UNDRAIN.
+    async fn forget_removed_backends(&self, configured: &HashSet<SocketAddr>) {
+        self.connection_counts.lock().await.retain(|addr, count| configured.contains(addr) || *count > 0);
+        self.current_weights.lock().await.retain(|(_, addr), _| configured.contains(addr));
+        self.health_streaks.lock().await.retain(|(addr, _), _| configured.contains(addr));
+        self.transitions.lock().await.retain(|(addr, _), _| configured.contains(addr));
+        self.probe_errors.lock().await.retain(|(addr, _), _| configured.contains(addr));
+        self.passive_strikes.lock().await.retain(|(addr, _), _| configured.contains(addr));
+        self.circuits.lock().await.retain(|addr, _| configured.contains(addr));
+        self.http_responses.lock().await.retain(|addr, _| configured.contains(addr));
+        self.outliers.lock().await.retain(|addr, _| configured.contains(addr));
+        self.latencies.lock().await.retain(|addr, _| configured.contains(addr));
+        self.backend_bytes.lock().await.retain(|addr, _| configured.contains(addr));
+        self.activated_at.lock().await.retain(|addr, _| configured.contains(addr));
+    }
+
+    // Passive health check: count a failed forward against the backend and eject it once it
+    // failed `passive_failures` times within the window. The active checks bring it back.
+    pub async fn record_failure(&self, backend: Backend) {
+        if self.health.passive_failures == 0 {
+            return;
+        }
+
+        {
+            let mut passive_strikes = self.passive_strikes.lock().await;
+            let strikes = passive_strikes.entry((backend.addr, backend.protocol)).or_default();
+            strikes.retain(|at| at.elapsed() < self.health.passive_window);
+            strikes.push(Instant::now());
+            if strikes.len() < self.health.passive_failures as usize {
+                return;
+            }
+            strikes.clear();
+        }
+
+        let mut active_backends = self.active_backends.lock().await;
+        let mut health_streaks = self.health_streaks.lock().await;
+        let mut ejected = false;
+        for active_ips in active_backends.values_mut() {
+            if let Some(pos) = active_ips.iter().position(|b| b.is_same(&backend)) {
+                active_ips.remove(pos);
+                ejected = true;
+            }
+        }
+        if ejected {
+            // Start counting towards `rise` from scratch
+            health_streaks.insert((backend.addr, backend.protocol), HealthStreak { successes: 0, failures: self.health.fall });
+            self.transitions.lock().await.entry((backend.addr, backend.protocol)).or_default().unhealthy = Some(Local::now());
+            log_event(
+                LogLevel::Warn,
+                "backend_ejected",
+                format!(
+                    "Backend {} failed {} times within {} seconds and is marked as unhealthy.",
+                    backend.addr,
+                    self.health.passive_failures,
+                    self.health.passive_window.as_secs()
+                ),
+                &[("backend", json!(backend.addr.to_string())), ("protocol", json!(format!("{:?}", backend.protocol)))],
+            );
+            self.track_all_down(&active_backends);
+        }
+    }
+
+    // Claim a selected backend for a connect. An open circuit whose cooldown passed turns half-open
+    // and lets this connect through as its trial; false if another connect already is the trial or the
+    // circuit is still cooling down.
+    pub async fn begin_connect(&self, addr: SocketAddr) -> bool {
+        if self.health.circuit_failures == 0 {
+            return true;
+        }
+        let mut circuits = self.circuits.lock().await;
+        match circuits.get(&addr) {
+            // The circuit may have opened since the backend was selected
+            Some(Circuit::Open { since }) if since.elapsed() < self.health.circuit_cooldown => false,
+            Some(Circuit::Open { .. }) => {
+                circuits.insert(addr, Circuit::HalfOpen);
+                log_debug(format!("Circuit of backend {} is half-open, letting a trial connection through", addr));
+                true
+            }
+            Some(Circuit::HalfOpen) => false,
+            _ => true,
+        }
+    }
+
+    // Circuit breaker: count the outcome of a connect. `circuit_failures` consecutive failures open
+    // the circuit, a failed trial reopens it and a successful one closes it again.
+    pub async fn record_connect(&self, addr: SocketAddr, connected: bool) {
+        if self.health.circuit_failures == 0 {
+            return;
+        }
+        let mut circuits = self.circuits.lock().await;
+        let circuit = circuits.entry(addr).or_insert(Circuit::Closed { failures: 0 });
+        let next = match (*circuit, connected) {
+            (Circuit::HalfOpen, true) => {
+                log_event(LogLevel::Info, "circuit_closed", format!("Trial connection to backend {} succeeded, circuit closed.", addr), &[("backend", json!(addr.to_string()))]);
+                Circuit::Closed { failures: 0 }
+            }
+            (_, true) => Circuit::Closed { failures: 0 },
+            (Circuit::Closed { failures }, false) if failures + 1 < self.health.circuit_failures => Circuit::Closed { failures: failures + 1 },
+            (Circuit::Open { since }, false) => Circuit::Open { since },
+            (previous, false) => {
+                let message = match previous {
+                    Circuit::HalfOpen => format!("Trial connection to backend {} failed, circuit reopened.", addr),
+                    _ => format!("Backend {} failed {} connects in a row, circuit opened.", addr, self.health.circuit_failures),
+                };
+                log_event(
+                    LogLevel::Warn,
+                    "circuit_open",
+                    message,
+                    &[("backend", json!(addr.to_string())), ("cooldown_secs", json!(self.health.circuit_cooldown.as_secs()))],
+                );
+                Circuit::Open { since: Instant::now() }
+            }
+        };
+        *circuit = next;
+    }
+
+    // Outlier detection: count the status of an HTTP response against the backend and eject it for
+    // the cooldown once its share of 5xx responses within the window reaches the configured ratio
+    pub async fn record_response(&self, backend: Backend, status: u16) {
+        if self.health.outlier_5xx_ratio == 0.0 {
+            return;
+        }
+
+        let ratio = {
+            let mut http_responses = self.http_responses.lock().await;
+            let responses = http_responses.entry(backend.addr).or_default();
+            responses.retain(|(at, _)| at.elapsed() < self.health.outlier_window);
+            responses.push((Instant::now(), status >= 500));
+            // A handful of responses says little, one 5xx out of two would already be 50%
+            if responses.len() < OUTLIER_MIN_RESPONSES {
+                return;
+            }
+            let ratio = responses.iter().filter(|(_, server_error)| *server_error).count() as f64 / responses.len() as f64;
+            if ratio < self.health.outlier_5xx_ratio {
+                return;
+            }
+            responses.clear();
+            ratio
+        };
+
+        let mut active_backends = self.active_backends.lock().await;
+        let mut ejected = false;
+        for active_ips in active_backends.values_mut() {
+            if let Some(pos) = active_ips.iter().position(|b| b.is_same(&backend)) {
+                active_ips.remove(pos);
+                ejected = true;
+            }
+        }
+        if ejected {
+            self.outliers.lock().await.insert(backend.addr, Instant::now());
+            self.transitions.lock().await.entry((backend.addr, backend.protocol)).or_default().unhealthy = Some(Local::now());
+            log_event(
+                LogLevel::Warn,
+                "backend_outlier",
+                format!(
+                    "Backend {} answered {:.0}% of the requests in the last {} seconds with a 5xx status and is ejected for {} seconds.",
+                    backend.addr,
+                    ratio * 100.0,
+                    self.health.outlier_window.as_secs(),
+                    self.health.outlier_cooldown.as_secs()
+                ),
+                &[("backend", json!(backend.addr.to_string())), ("ratio", json!(ratio))],
+            );
+            self.track_all_down(&active_backends);
+        }
+    }
+
+    pub fn start_draining(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    pub async fn active_connections(&self) -> usize {
+        self.connection_counts.lock().await.values().sum()
+    }
+
+    // Wait until every tracked connection finished, returns false if the timeout hit first
+    pub async fn wait_for_drain(&self, drain_timeout: Duration) -> bool {
+        let deadline = Instant::now() + drain_timeout;
+        while self.active_connections().await > 0 {
+            if Instant::now() >= deadline {
+                return false;
             }
+            sleep(Duration::from_millis(100)).await;
         }
+        true
     }
 
     pub async fn perform_health_checks(&self) {
+        let probe_slots = Arc::new(Semaphore::new(self.health.concurrency));
+
         loop {
-            sleep(Duration::from_secs(10)).await;  // Perform health checks every 10 seconds
-            let backends = self.backends.lock().await.clone();
+            // When traffic depends on the first round it runs right away. Backends were never confirmed
+            // before it, so with wait_for_health one failure is enough to leave them out
+            let first_round = *self.health_sweeps.borrow() == 0;
+            if !(first_round && (self.wait_for_health || self.strict_health)) {
+                sleep(jittered(self.health.interval, self.health.jitter)).await;
+            }
 
-            for (hostname, ips) in backends {
+            // No new connections are routed while draining, the active set no longer matters
+            if self.is_draining() {
+                break;
+            }
+
+            // An address listed in several groups is probed once and its result applies to all of them
+            let mut targets: HashMap<(SocketAddr, Protocol), (Backend, Vec<String>)> = HashMap::new();
+            for (hostname, ips) in self.backends.lock().await.iter() {
                 for backend in ips {
-                    match backend.protocol {
-                        Protocol::TCP => {
-                            match TcpStream::connect(backend.addr).await {
-                                Ok(_) => {
-                                    // Backend is reachable, ensure it is in the active list
-                                    let mut active_backends = self.active_backends.lock().await;
-                                    let active_ips = active_backends.entry(hostname.clone()).or_insert_with(Vec::new);
-                                    if !active_ips.iter().any(|b| b.addr == backend.addr) {
-                                        active_ips.push(backend);
-                                        log(format!("Backend {} is back online and marked as healthy.", backend.addr));
-                                    }
-                                }
-                                Err(_) => {
-                                    // Backend is unreachable, remove it from the active list
-                                    let mut active_backends = self.active_backends.lock().await;
-                                    if let Some(active_ips) = active_backends.get_mut(&hostname) {
-                                        if let Some(pos) = active_ips.iter().position(|b| b.addr == backend.addr) {
-                                            active_ips.remove(pos);
-                                            log(format!("Backend {} is offline and marked as unhealthy.", backend.addr));
-                                        }
-                                    }
-                                }
+                    targets.entry((backend.addr, backend.protocol)).or_insert((*backend, Vec::new())).1.push(hostname.clone());
+                }
+            }
+
+            // Probe all backends concurrently, bounded by the configured concurrency
+            let mut probes = JoinSet::new();
+            for (backend, hostnames) in targets.into_values() {
+                let probe_slots = probe_slots.clone();
+                let probe_timeout = self.health.timeout;
+                let http_path = self.health.http_path.clone();
+                let health = self.health.clone();
+                let source = self.source_addr;
+                probes.spawn(async move {
+                    let _slot = probe_slots.acquire_owned().await.ok()?;
+                    let started = Instant::now();
+                    let probe = match (backend.protocol, http_path) {
+                        #[cfg(feature = "grpc")]
+                        (Protocol::TCP, _) if health.grpc_service.is_some() => {
+                            probe_grpc(backend.addr, source, health.grpc_service.as_deref().unwrap_or_default(), probe_timeout).await
+                        }
+                        (Protocol::TCP, Some(path)) => probe_http(backend.addr, source, &path, probe_timeout).await,
+                        (Protocol::TCP, None) => probe_tcp(backend.addr, source, probe_timeout).await,
+                        (Protocol::UDP, _) => probe_udp(backend.addr, source, &health).await,
+                    };
+                    Some((hostnames, backend, probe, started.elapsed()))
+                });
+            }
+
+            let mut results = Vec::new();
+            while let Some(result) = probes.join_next().await {
+                if let Ok(Some((hostnames, backend, probe, latency))) = result {
+                    // A UDP probe only sends a datagram, its duration says nothing about the backend
+                    if probe.is_ok() && backend.protocol == Protocol::TCP {
+                        self.record_latency(backend.addr, latency).await;
+                    }
+                    results.push((hostnames, backend, probe));
+                }
+            }
+
+            // Apply all results under a single lock so the active list is updated in one pass
+            let configured = self.backends.lock().await;
+            let mut active_backends = self.active_backends.lock().await;
+            let mut health_streaks = self.health_streaks.lock().await;
+            let admin_drained = self.admin_drained.lock().await;
+            let removing = self.removing.lock().await;
+            let mut outliers = self.outliers.lock().await;
+            let mut activated_at = self.activated_at.lock().await;
+            let mut transitions = self.transitions.lock().await;
+            let mut probe_errors = self.probe_errors.lock().await;
+            if let Some(window) = self.slow_start {
+                activated_at.retain(|_, at| at.elapsed() < window);
+            }
+            for (hostnames, probed, probe) in results {
+                let healthy = probe.is_ok();
+                // Backends removed while their probe was running must not come back
+                let members: Vec<(String, Backend)> = hostnames
+                    .into_iter()
+                    .filter_map(|hostname| {
+                        let member = *configured.get(&hostname)?.iter().find(|b| b.is_same(&probed))?;
+                        Some((hostname, member))
+                    })
+                    .collect();
+                if members.is_empty() {
+                    continue;
+                }
+                let streak = health_streaks.entry((probed.addr, probed.protocol)).or_default();
+                streak.record(healthy);
+                let streak = *streak;
+                if healthy {
+                    Metrics::add(&self.metrics.health_checks_passed, 1);
+                } else {
+                    Metrics::add(&self.metrics.health_checks_failed, 1);
+                }
+
+                // Each group keeps its own entry of the backend, e.g. with a different weight
+                for (hostname, backend) in members {
+
+                    if healthy {
+                        if streak.successes < self.health.rise
+                            || admin_drained.contains(&backend.addr)
+                            || removing.contains_key(&(hostname.clone(), backend.addr, backend.protocol))
+                            || outliers.get(&backend.addr).is_some_and(|at| at.elapsed() < self.health.outlier_cooldown)
+                        {
+                            continue;
+                        }
+                        outliers.remove(&backend.addr);
+
+                        // Backend is reachable, ensure it is in the active list
+                        let active_ips = active_backends.entry(hostname.clone()).or_default();
+                        if !active_ips.iter().any(|b| b.is_same(&backend)) {
+                            active_ips.push(backend);
+                            if self.slow_start.is_some() {
+                                activated_at.insert(backend.addr, Instant::now());
                             }
+                            transitions.entry((backend.addr, backend.protocol)).or_default().healthy = Some(Local::now());
+                            let message = match backend.protocol {
+                                Protocol::TCP => format!("Backend {} is back online and marked as healthy.", backend.addr),
+                                Protocol::UDP => format!("UDP Backend {} is back online and marked as healthy.", backend.addr),
+                            };
+                            log_event(
+                                LogLevel::Info,
+                                "backend_healthy",
+                                message,
+                                &[("group", json!(hostname)), ("backend", json!(backend.addr.to_string())), ("protocol", json!(format!("{:?}", backend.protocol)))],
+                            );
                         }
-                        Protocol::UDP => {
-                            // Perform UDP health check by attempting to bind a UDP socket
-                            match UdpSocket::bind("0.0.0.0:0").await {
-                                Ok(udp_socket) => {
-                                    let health_check_msg = b"health-check";
-                                    if udp_socket.send_to(health_check_msg, backend.addr).await.is_ok() {
-                                        // Backend is reachable, ensure it is in the active list
-                                        let mut active_backends = self.active_backends.lock().await;
-                                        let active_ips = active_backends.entry(hostname.clone()).or_insert_with(Vec::new);
-                                        if !active_ips.iter().any(|b| b.addr == backend.addr) {
-                                            active_ips.push(backend);
-                                            log(format!("UDP Backend {} is back online and marked as healthy.", backend.addr));
-                                        }
-                                    } else {
-                                        log(format!("UDP Backend {} is not responding.", backend.addr));
-                                    }
-                                }
-                                Err(_) => {
-                                    log(format!("Failed to bind UDP socket for health check on backend {}", backend.addr));
+                    } else if let Err(error) = probe {
+                        probe_errors.insert((backend.addr, backend.protocol), error);
+                        // Without a reply to wait for, a UDP probe only fails conclusively on an ICMP error,
+                        // anything else is a local problem
+                        let conclusive = backend.protocol == Protocol::TCP
+                            || self.health.expects_udp_reply()
+                            || matches!(error, ProbeError::Refused | ProbeError::Unreachable);
+                        if conclusive && (streak.failures >= self.health.fall || (first_round && self.wait_for_health)) {
+                            // Backend is unreachable or timed out, remove it from the active list
+                            if let Some(active_ips) = active_backends.get_mut(&hostname) {
+                                if let Some(pos) = active_ips.iter().position(|b| b.is_same(&backend)) {
+                                    active_ips.remove(pos);
+                                    transitions.entry((backend.addr, backend.protocol)).or_default().unhealthy = Some(Local::now());
+                                    log_event(
+                                        LogLevel::Info,
+                                        "backend_unhealthy",
+                                        format!("Backend {} is offline and marked as unhealthy ({}).", backend.addr, error),
+                                        &[
+                                            ("group", json!(hostname)),
+                                            ("backend", json!(backend.addr.to_string())),
+                                            ("protocol", json!(format!("{:?}", backend.protocol))),
+                                            ("error", json!(error.to_string())),
+                                        ],
+                                    );
                                 }
                             }
+                        } else if conclusive {
+                            log_debug(format!(
+                                "Health check of backend {} failed ({}), {} of {} failures before it is marked as unhealthy",
+                                backend.addr, error, streak.failures, self.health.fall
+                            ));
+                        } else {
+                            log_warn(format!("UDP Backend {} is not responding ({}).", backend.addr, error));
                         }
                     }
                 }
             }
+            self.health_sweeps.send_modify(|sweeps| *sweeps += 1);
+            self.track_all_down(&active_backends);
+            drop((configured, active_backends));
+        }
+    }
+}
+
+// One live connection to a backend, counted from LoadBalancer::track_connection until dropped
+pub struct ConnectionGuard {
+    lb: Arc<LoadBalancer>,
+    addr: SocketAddr,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        // Drop cannot wait for the lock, so only a contended release is handed to a task
+        if let Ok(mut connection_counts) = self.lb.connection_counts.try_lock() {
+            release_connection(&mut connection_counts, self.addr);
+        } else if let Ok(runtime) = Handle::try_current() {
+            let (lb, addr) = (self.lb.clone(), self.addr);
+            runtime.spawn(async move {
+                release_connection(&mut *lb.connection_counts.lock().await, addr);
+            });
         }
     }
 }
 
-// Helper function to detect the protocol dynamically by attempting to connect to the backend
+// Point out addresses listed in more than one group. They are probed, counted and selected once,
+// as a member of the first of their groups by label.
+fn log_shared_backends(backends: &HashMap<String, Vec<Backend>>) {
+    let mut groups_of: HashMap<(SocketAddr, Protocol), Vec<&String>> = HashMap::new();
+    for (label, members) in backends {
+        for backend in members {
+            groups_of.entry((backend.addr, backend.protocol)).or_default().push(label);
+        }
+    }
+    for ((addr, protocol), mut labels) in groups_of.into_iter().filter(|(_, labels)| labels.len() > 1) {
+        labels.sort();
+        let list: Vec<&str> = labels.iter().map(|label| label.as_str()).collect();
+        log_warn(format!(
+            "Backend {} ({:?}) is listed in groups {}, it is selected as a member of {} only",
+            addr,
+            protocol,
+            list.join(", "),
+            labels[0]
+        ));
+    }
+}
+
+fn release_connection(connection_counts: &mut HashMap<SocketAddr, usize>, addr: SocketAddr) {
+    if let Some(count) = connection_counts.get_mut(&addr) {
+        if *count > 0 {
+            *count -= 1;
+        }
+    }
+}
+
+// Helper function to detect the protocol dynamically by attempting to connect to the backend.
+//
+// TCP is detected by a completed handshake. UDP is connectionless, so "detected" only means
+// the port did not actively refuse a probe: a connected UDP socket surfaces an ICMP port
+// unreachable as a receive error, which marks the port as closed. A reply proves a listener,
+// silence until the timeout is inconclusive (open or filtered) and is treated as UDP as well,
+// since the TCP handshake already failed.
 pub async fn detect_protocol(addr: SocketAddr) -> Option<Protocol> {
     // Test TCP connection first
-    if TcpStream::connect(addr).await.is_ok() {
+    if let Ok(Ok(_)) = timeout(DETECT_TIMEOUT, TcpStream::connect(addr)).await {
         return Some(Protocol::TCP);
     }
 
-    // If TCP fails, test UDP connection by attempting to send a small message
-    if let Ok(socket) = UdpSocket::bind("0.0.0.0:0").await {
-        let test_msg = b"protocol_test";
-        if socket.send_to(test_msg, addr).await.is_ok() {
-            return Some(Protocol::UDP);
+    // If TCP fails, probe UDP and watch for an ICMP port unreachable
+    let socket = UdpSocket::bind(unspecified_addr_for(&addr)).await.ok()?;
+    socket.connect(addr).await.ok()?;
+    socket.send(b"protocol_test").await.ok()?;
+
+    let mut buf = [0u8; 512];
+    match timeout(DETECT_TIMEOUT, socket.recv(&mut buf)).await {
+        Ok(Ok(_)) => Some(Protocol::UDP),   // Got a reply, something is listening
+        Ok(Err(_)) => None,                 // Port unreachable, nothing is listening
+        Err(_) => Some(Protocol::UDP),      // No answer, open or filtered
+    }
+}
+
+// Read the drained addresses saved by an earlier run, one per line. A missing file means nothing
+// was drained yet.
+pub fn load_drain_state(path: &Path) -> io::Result<HashSet<SocketAddr>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashSet::new()),
+        Err(e) => return Err(e),
+    };
+    let mut drained = HashSet::new();
+    for line in contents.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        match line.parse() {
+            Ok(addr) => {
+                drained.insert(addr);
+            }
+            Err(_) => log_warn(format!("Ignoring invalid address {} in {}", line, path.display())),
         }
     }
+    Ok(drained)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backend(addr: &str, weight: u32) -> Backend {
+        Backend { addr: addr.parse().unwrap(), protocol: Protocol::TCP, weight }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn weighted_round_robin_interleaves() {
+        let lb = LoadBalancer::new(LoadBalancerMode::RoundRobin, HealthCheckConfig::default());
+        let (a, b, c) = (backend("10.0.0.1:80", 5), backend("10.0.0.2:80", 1), backend("10.0.0.3:80", 1));
 
-    // If both tests fail, return None
-    None
+        let mut picked = Vec::new();
+        for _ in 0..14 {
+            let selected = lb.select(LoadBalancerMode::RoundRobin, "", vec![a, b, c]).await.unwrap();
+            picked.push(selected.addr);
+        }
+
+        // Two full cycles of the nginx order, never a burst of five for the heavy backend
+        let cycle = [a.addr, a.addr, b.addr, a.addr, c.addr, a.addr, a.addr];
+        assert_eq!(picked, [cycle, cycle].concat());
+    }
 }
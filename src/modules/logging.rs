@@ -0,0 +1,207 @@
+use chrono::Local;
+use serde_json::{Map, Value};
+use std::fs::{File, OpenOptions};
+use std::io::{LineWriter, Write};
+use std::sync::{Mutex, OnceLock};
+
+// Output format of every log line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,  // `[timestamp] message`
+    Json,  // One JSON object per line
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<LogFormat, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+impl std::fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            LogFormat::Text => "text",
+            LogFormat::Json => "json",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Severity of a log line, ordered from most to least severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl std::str::FromStr for LogLevel {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<LogLevel, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            _ => Err(()),
+        }
+    }
+}
+
+impl LogLevel {
+    fn name(self) -> &'static str {
+        match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+        }
+    }
+}
+
+impl std::fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+// Where and how log lines are written
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    pub format: LogFormat,
+    pub level: LogLevel,       // Lines below this severity are dropped
+    pub file: Option<String>,  // Append every line to this file as well
+    pub stdout: bool,          // Write to stdout/stderr, may be disabled when logging to a file
+    pub max_size: u64,         // Size in bytes after which the log file is rotated
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            format: LogFormat::Text,
+            level: LogLevel::Info,
+            file: None,
+            stdout: true,
+            max_size: 10 * 1024 * 1024,
+        }
+    }
+}
+
+// Log file with size-based rotation, the previous file is kept as `<path>.1`
+struct LogFile {
+    path: String,
+    writer: LineWriter<File>,
+    size: u64,
+    max_size: u64,
+}
+
+impl LogFile {
+    fn open(path: &str, max_size: u64) -> std::io::Result<LogFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(LogFile { path: path.to_string(), writer: LineWriter::new(file), size, max_size })
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if self.size > 0 && self.size + line.len() as u64 + 1 > self.max_size {
+            self.rotate();
+        }
+        if writeln!(self.writer, "{}", line).is_ok() {
+            self.size += line.len() as u64 + 1;
+        }
+    }
+
+    fn rotate(&mut self) {
+        let _ = self.writer.flush();
+        let _ = std::fs::rename(&self.path, format!("{}.1", self.path));
+        // Keep writing to the old handle if a new file cannot be created
+        if let Ok(file) = OpenOptions::new().create(true).append(true).open(&self.path) {
+            self.writer = LineWriter::new(file);
+            self.size = 0;
+        }
+    }
+}
+
+static LOG_CONFIG: OnceLock<LogConfig> = OnceLock::new();
+static LOG_FILE: OnceLock<Mutex<LogFile>> = OnceLock::new();
+
+// Apply the logging settings once at startup, lines logged before go to stdout as text at info level
+pub fn init_logging(config: LogConfig) -> Result<(), String> {
+    if let Some(path) = &config.file {
+        let file = LogFile::open(path, config.max_size).map_err(|e| format!("Failed to open log file {}: {}", path, e))?;
+        let _ = LOG_FILE.set(Mutex::new(file));
+    }
+    let _ = LOG_CONFIG.set(config);
+    Ok(())
+}
+
+fn log_format() -> LogFormat {
+    LOG_CONFIG.get().map_or(LogFormat::Text, |config| config.format)
+}
+
+fn log_enabled(level: LogLevel) -> bool {
+    level <= LOG_CONFIG.get().map_or(LogLevel::Info, |config| config.level)
+}
+
+pub fn log(message: String) {
+    log_event(LogLevel::Info, "message", message, &[]);
+}
+
+pub fn log_debug(message: String) {
+    log_event(LogLevel::Debug, "message", message, &[]);
+}
+
+pub fn log_warn(message: String) {
+    log_event(LogLevel::Warn, "message", message, &[]);
+}
+
+pub fn log_error(message: String) {
+    log_event(LogLevel::Error, "message", message, &[]);
+}
+
+// Log an event with structured context. Text output only shows the message, JSON output carries
+// the event name and every field as its own key next to the message. Warnings and errors go to stderr.
+pub fn log_event(level: LogLevel, event: &str, message: String, fields: &[(&str, Value)]) {
+    if !log_enabled(level) {
+        return;
+    }
+
+    let now = Local::now();
+    let line = match log_format() {
+        LogFormat::Text if level == LogLevel::Info => format!("[{}] {}", now.format("%Y-%m-%d %H:%M:%S"), message),
+        LogFormat::Text => format!("[{}] {} {}", now.format("%Y-%m-%d %H:%M:%S"), level.name().to_uppercase(), message),
+        LogFormat::Json => {
+            let mut line = Map::new();
+            line.insert("ts".to_string(), now.to_rfc3339().into());
+            line.insert("level".to_string(), level.name().into());
+            line.insert("event".to_string(), event.into());
+            line.insert("msg".to_string(), message.into());
+            for (key, value) in fields {
+                line.insert(key.to_string(), value.clone());
+            }
+            Value::Object(line).to_string()
+        }
+    };
+
+    if let Some(file) = LOG_FILE.get() {
+        if let Ok(mut file) = file.lock() {
+            file.write_line(&line);
+        }
+    }
+    if LOG_CONFIG.get().is_some_and(|config| !config.stdout) {
+        return;
+    }
+    if level <= LogLevel::Warn {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{timeout, Duration};
+use crate::modules::load_balancer::{LoadBalancer, Protocol};
+use crate::modules::logging::{log, log_error, log_warn};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5); // Time a scraper has to send its request
+
+// Process-wide counters, cheap enough to always be updated
+#[derive(Default)]
+pub struct Metrics {
+    pub connections_total: AtomicU64,
+    pub bytes_client_to_backend: AtomicU64,
+    pub bytes_backend_to_client: AtomicU64,
+    pub health_checks_passed: AtomicU64,
+    pub health_checks_failed: AtomicU64,
+}
+
+impl Metrics {
+    pub fn add(counter: &AtomicU64, value: u64) {
+        counter.fetch_add(value, Ordering::Relaxed);
+    }
+}
+
+// Serve the Prometheus text format on `GET /metrics`
+pub async fn run_metrics_server(addr: SocketAddr, lb: Arc<LoadBalancer>) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            log_error(format!("Failed to bind metrics endpoint on {}: {:?}", addr, e));
+            return;
+        }
+    };
+    log(format!("Metrics endpoint started on: http://{}/metrics", addr));
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let lb = lb.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve_metrics(stream, lb).await {
+                        log_warn(format!("Failed to serve metrics request: {:?}", e));
+                    }
+                });
+            }
+            Err(e) => log_warn(format!("Failed to accept metrics connection: {:?}", e)),
+        }
+    }
+}
+
+async fn serve_metrics(mut stream: TcpStream, lb: Arc<LoadBalancer>) -> std::io::Result<()> {
+    let mut buf = [0u8; 1024];
+    // A client that connects and never sends anything would hold the task and socket forever
+    let Ok(len) = timeout(REQUEST_TIMEOUT, stream.read(&mut buf)).await else {
+        return Ok(());
+    };
+    let len = len?;
+    let request = String::from_utf8_lossy(&buf[..len]);
+
+    let response = if request.starts_with("GET /metrics ") {
+        let body = render_metrics(&lb).await;
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+async fn render_metrics(lb: &LoadBalancer) -> String {
+    let metrics = &lb.metrics;
+    let mut out = String::new();
+
+    let counters = [
+        ("sidelb_connections_total", "Connections accepted and forwarded to a backend.", &metrics.connections_total),
+        ("sidelb_bytes_client_to_backend_total", "Bytes forwarded from clients to backends.", &metrics.bytes_client_to_backend),
+        ("sidelb_bytes_backend_to_client_total", "Bytes forwarded from backends to clients.", &metrics.bytes_backend_to_client),
+        ("sidelb_health_checks_passed_total", "Health checks that succeeded.", &metrics.health_checks_passed),
+        ("sidelb_health_checks_failed_total", "Health checks that failed.", &metrics.health_checks_failed),
+    ];
+    for (name, help, counter) in counters {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        let _ = writeln!(out, "{} {}", name, counter.load(Ordering::Relaxed));
+    }
+
+    let backends = lb.backends.lock().await.clone();
+    let active_backends = lb.active_backends.lock().await.clone();
+    let connection_counts = lb.connection_counts.lock().await.clone();
+    let backend_bytes = lb.backend_bytes.lock().await.clone();
+
+    let _ = writeln!(out, "# HELP sidelb_backend_up Whether the backend is currently active (1) or not (0).");
+    let _ = writeln!(out, "# TYPE sidelb_backend_up gauge");
+    for (group, ips) in &backends {
+        for backend in ips {
+            let up = active_backends
+                .get(group)
+                .is_some_and(|active| active.iter().any(|b| b.is_same(backend)));
+            let _ = writeln!(
+                out,
+                "sidelb_backend_up{{group=\"{}\",backend=\"{}\",protocol=\"{:?}\"}} {}",
+                group, backend.addr, backend.protocol, up as u8
+            );
+        }
+    }
+
+    let _ = writeln!(out, "# HELP sidelb_backend_active_connections Live connections per backend.");
+    let _ = writeln!(out, "# TYPE sidelb_backend_active_connections gauge");
+    // Connections are counted per address, a backend used for TCP and UDP gets one sample per group
+    for (group, ips) in &backends {
+        let mut seen = HashSet::new();
+        for backend in ips.iter().filter(|b| seen.insert(b.addr)) {
+            let count = connection_counts.get(&backend.addr).copied().unwrap_or(0);
+            let _ = writeln!(out, "sidelb_backend_active_connections{{group=\"{}\",backend=\"{}\"}} {}", group, backend.addr, count);
+        }
+    }
+
+    // Totals are per address, a backend listed in several groups reports the same value in each
+    let per_backend_bytes = [
+        ("sidelb_backend_bytes_client_to_backend_total", "Bytes forwarded from clients to the backend.", true),
+        ("sidelb_backend_bytes_backend_to_client_total", "Bytes forwarded from the backend to clients.", false),
+    ];
+    for (name, help, upstream) in per_backend_bytes {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for (group, ips) in &backends {
+            for backend in ips.iter().filter(|b| b.protocol == Protocol::TCP) {
+                let (up, down) = backend_bytes.get(&backend.addr).copied().unwrap_or_default();
+                let bytes = if upstream { up } else { down };
+                let _ = writeln!(out, "{}{{group=\"{}\",backend=\"{}\"}} {}", name, group, backend.addr, bytes);
+            }
+        }
+    }
+
+    out
+}
@@ -2,3 +2,20 @@ pub mod load_balancer;
 pub mod handlers;
 pub mod utils;
 pub mod dns;
+pub mod hashing;
+pub mod health;
+pub mod metrics;
+pub mod http_health;
+pub mod uds;
+pub mod config;
+pub mod proxy_protocol;
+pub mod sni;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod logging;
+pub mod sockets;
+pub mod pool;
+pub mod rate_limit;
+pub mod reload;
@@ -0,0 +1,114 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::mem::MaybeUninit;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use socket2::SockRef;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio::time::{sleep, timeout, Duration, Instant};
+use crate::modules::logging::log_debug;
+use crate::modules::sockets::{configure_tcp_stream, connect_tcp};
+
+// Settings of the pre-connected TCP pool
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    pub size: usize,  // Idle connections kept ready per backend
+    pub idle_timeout: Duration,  // Close a pooled connection that was not handed out for this long
+    pub connect_timeout: Duration,
+    pub source_addr: Option<IpAddr>,
+    pub nodelay: bool,
+    pub keepalive: Option<Duration>,
+}
+
+#[derive(Default)]
+struct BackendPool {
+    idle: VecDeque<(TcpStream, Instant)>,  // Established and never used, oldest first
+    connecting: usize,  // Refills in flight, so concurrent refills do not overshoot the size
+}
+
+// Connections opened ahead of time, so a new client skips the backend handshake. A pooled
+// connection is only ever handed to one client: backends keep per-connection state (sessions,
+// authentication, the PROXY header or replayed ClientHello), so a used connection is never reused.
+pub struct ConnectionPool {
+    config: PoolConfig,
+    backends: Mutex<HashMap<SocketAddr, BackendPool>>,
+}
+
+impl ConnectionPool {
+    pub fn new(config: PoolConfig) -> Self {
+        ConnectionPool { config, backends: Mutex::new(HashMap::new()) }
+    }
+
+    // Take the most recently opened connection to `addr` that is still open, and tell whether
+    // the pool of `addr` is short of connections afterwards and needs a refill
+    pub async fn take(&self, addr: SocketAddr) -> (Option<TcpStream>, bool) {
+        let mut backends = self.backends.lock().await;
+        let Some(pool) = backends.get_mut(&addr) else {
+            return (None, true);
+        };
+        let mut taken = None;
+        while let Some((stream, opened)) = pool.idle.pop_back() {
+            if opened.elapsed() < self.config.idle_timeout && is_open(&stream) {
+                taken = Some(stream);
+                break;
+            }
+        }
+        (taken, pool.idle.len() + pool.connecting < self.config.size)
+    }
+
+    // Open connections to `addr` until its pool is full again
+    pub async fn refill(self: Arc<Self>, addr: SocketAddr) {
+        let missing = {
+            let mut backends = self.backends.lock().await;
+            let pool = backends.entry(addr).or_default();
+            let missing = self.config.size.saturating_sub(pool.idle.len() + pool.connecting);
+            pool.connecting += missing;
+            missing
+        };
+
+        for opened in 0..missing {
+            let connected = match timeout(self.config.connect_timeout, connect_tcp(addr, self.config.source_addr)).await {
+                Ok(Ok(stream)) => configure_tcp_stream(&stream, self.config.nodelay, self.config.keepalive).map(|_| stream),
+                Ok(Err(e)) => Err(e),
+                Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "connect timed out")),
+            };
+
+            let mut backends = self.backends.lock().await;
+            let pool = backends.entry(addr).or_default();
+            pool.connecting = pool.connecting.saturating_sub(1);
+            match connected {
+                Ok(stream) => pool.idle.push_back((stream, Instant::now())),
+                Err(e) => {
+                    // The forwarding path reports failing backends, the pool just stops trying
+                    log_debug(format!("Failed to open pooled connection to {}: {:?}", addr, e));
+                    pool.connecting = pool.connecting.saturating_sub(missing - opened - 1);
+                    break;
+                }
+            }
+        }
+    }
+
+    // Close pooled connections that waited longer than the idle timeout
+    pub async fn evict_idle(&self) {
+        loop {
+            sleep(Duration::from_secs(1)).await;
+            let mut backends = self.backends.lock().await;
+            for pool in backends.values_mut() {
+                pool.idle.retain(|(_, opened)| opened.elapsed() < self.config.idle_timeout);
+            }
+            backends.retain(|_, pool| !pool.idle.is_empty() || pool.connecting > 0);
+        }
+    }
+}
+
+// A pooled connection is useless once the backend closed it. Data waiting on it (e.g. a server
+// greeting) is fine, it stays in the socket for the client.
+fn is_open(stream: &TcpStream) -> bool {
+    let mut buf = [MaybeUninit::<u8>::uninit(); 1];
+    match SockRef::from(stream).peek(&mut buf) {
+        Ok(0) => false,
+        Ok(_) => true,
+        Err(e) => e.kind() == io::ErrorKind::WouldBlock,
+    }
+}
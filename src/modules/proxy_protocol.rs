@@ -0,0 +1,150 @@
+use std::net::{IpAddr, SocketAddr};
+
+// Fixed 12 byte signature that starts every PROXY protocol v2 header
+const V2_SIGNATURE: [u8; 12] = [0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A];
+
+// PROXY protocol version sent to backends ahead of the forwarded stream
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProxyProtocol {
+    V1,
+    V2,
+}
+
+impl std::str::FromStr for ProxyProtocol {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<ProxyProtocol, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "v1" => Ok(ProxyProtocol::V1),
+            "v2" => Ok(ProxyProtocol::V2),
+            _ => Err(()),
+        }
+    }
+}
+
+impl std::fmt::Display for ProxyProtocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ProxyProtocol::V1 => "v1",
+            ProxyProtocol::V2 => "v2",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Both addresses of a header must share a family, an IPv4 address next to an IPv6 one is
+// sent in its IPv4-mapped IPv6 form
+fn same_family(source: SocketAddr, destination: SocketAddr) -> (SocketAddr, SocketAddr) {
+    let to_v6 = |addr: SocketAddr| match addr.ip() {
+        IpAddr::V4(ip) => SocketAddr::new(IpAddr::V6(ip.to_ipv6_mapped()), addr.port()),
+        IpAddr::V6(_) => addr,
+    };
+    if source.is_ipv4() == destination.is_ipv4() {
+        (source, destination)
+    } else {
+        (to_v6(source), to_v6(destination))
+    }
+}
+
+// Build the header announcing a TCP connection from `source` to `destination`
+pub fn proxy_header(version: ProxyProtocol, source: SocketAddr, destination: SocketAddr) -> Vec<u8> {
+    let (source, destination) = same_family(source, destination);
+    match version {
+        ProxyProtocol::V1 => {
+            let family = if source.is_ipv4() { "TCP4" } else { "TCP6" };
+            format!(
+                "PROXY {} {} {} {} {}\r\n",
+                family,
+                source.ip(),
+                destination.ip(),
+                source.port(),
+                destination.port()
+            )
+            .into_bytes()
+        }
+        ProxyProtocol::V2 => {
+            let mut header = V2_SIGNATURE.to_vec();
+            header.push(0x21); // Version 2, PROXY command
+
+            match (source.ip(), destination.ip()) {
+                (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                    header.push(0x11); // TCP over IPv4
+                    header.extend_from_slice(&12u16.to_be_bytes());
+                    header.extend_from_slice(&src.octets());
+                    header.extend_from_slice(&dst.octets());
+                }
+                (IpAddr::V6(src), IpAddr::V6(dst)) => {
+                    header.push(0x21); // TCP over IPv6
+                    header.extend_from_slice(&36u16.to_be_bytes());
+                    header.extend_from_slice(&src.octets());
+                    header.extend_from_slice(&dst.octets());
+                }
+                _ => unreachable!("addresses were brought to the same family"),
+            }
+            header.extend_from_slice(&source.port().to_be_bytes());
+            header.extend_from_slice(&destination.port().to_be_bytes());
+            header
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+    fn addr(addr: &str) -> SocketAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn v1_tcp4() {
+        let header = proxy_header(ProxyProtocol::V1, addr("192.168.0.1:56324"), addr("10.0.0.2:443"));
+        assert_eq!(header, b"PROXY TCP4 192.168.0.1 10.0.0.2 56324 443\r\n");
+    }
+
+    #[test]
+    fn v1_tcp6() {
+        let header = proxy_header(ProxyProtocol::V1, addr("[2001:db8::1]:56324"), addr("[::1]:443"));
+        assert_eq!(header, b"PROXY TCP6 2001:db8::1 ::1 56324 443\r\n");
+    }
+
+    #[test]
+    fn v1_mixed_family_uses_mapped_addresses() {
+        let header = proxy_header(ProxyProtocol::V1, addr("192.168.0.1:56324"), addr("[2001:db8::2]:443"));
+        assert_eq!(header, b"PROXY TCP6 ::ffff:192.168.0.1 2001:db8::2 56324 443\r\n");
+    }
+
+    #[test]
+    fn v2_ipv4() {
+        let header = proxy_header(ProxyProtocol::V2, addr("192.168.0.1:56324"), addr("10.0.0.2:443"));
+        let mut expected = SIGNATURE.to_vec();
+        expected.extend_from_slice(&[0x21, 0x11, 0x00, 0x0C]);
+        expected.extend_from_slice(&[192, 168, 0, 1, 10, 0, 0, 2]);
+        expected.extend_from_slice(&[0xDC, 0x04, 0x01, 0xBB]);
+        assert_eq!(header, expected);
+    }
+
+    #[test]
+    fn v2_ipv6() {
+        let header = proxy_header(ProxyProtocol::V2, addr("[2001:db8::1]:56324"), addr("[::1]:443"));
+        let mut expected = SIGNATURE.to_vec();
+        expected.extend_from_slice(&[0x21, 0x21, 0x00, 0x24]);
+        expected.extend_from_slice(&[0x20, 0x01, 0x0D, 0xB8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        expected.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        expected.extend_from_slice(&[0xDC, 0x04, 0x01, 0xBB]);
+        assert_eq!(header, expected);
+    }
+
+    #[test]
+    fn v2_mixed_family_uses_mapped_addresses() {
+        let header = proxy_header(ProxyProtocol::V2, addr("192.168.0.1:56324"), addr("[2001:db8::2]:443"));
+        let mut expected = SIGNATURE.to_vec();
+        expected.extend_from_slice(&[0x21, 0x21, 0x00, 0x24]);
+        expected.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF, 192, 168, 0, 1]);
+        expected.extend_from_slice(&[0x20, 0x01, 0x0D, 0xB8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+        expected.extend_from_slice(&[0xDC, 0x04, 0x01, 0xBB]);
+        assert_eq!(header, expected);
+    }
+}
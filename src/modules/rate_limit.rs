@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant};
+use crate::modules::logging::{log, log_warn};
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10); // How often buckets of idle clients are dropped
+
+// Token bucket of one client IP, refilled at the configured rate up to one second's worth
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    rejected: u64,  // Connections rejected since the client last got one through
+}
+
+// Limits how many new TCP connections each client IP may open per second (conn_rate=)
+pub struct ConnRateLimiter {
+    rate: f64,
+    buckets: Mutex<(HashMap<IpAddr, Bucket>, Instant)>,  // Buckets by client and when idle ones were last dropped
+}
+
+impl ConnRateLimiter {
+    pub fn new(per_second: u32) -> Self {
+        ConnRateLimiter {
+            rate: per_second as f64,
+            buckets: Mutex::new((HashMap::new(), Instant::now())),
+        }
+    }
+
+    // Take a token for a new connection from `ip`, false if its bucket is empty.
+    // A client is logged once when it starts being limited and once when it gets through again.
+    pub async fn allow(&self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        let mut guard = self.buckets.lock().await;
+        let (buckets, swept) = &mut *guard;
+        // A bucket that refilled completely is no different from a new one, so it can go. A limited
+        // client that went quiet is below the rate again, which is logged before its count is lost.
+        if now.duration_since(*swept) >= SWEEP_INTERVAL {
+            buckets.retain(|ip, bucket| {
+                let refilled = bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * self.rate >= self.rate;
+                if refilled && bucket.rejected > 0 {
+                    log_recovered(*ip, bucket.rejected);
+                }
+                !refilled
+            });
+            *swept = now;
+        }
+
+        let bucket = buckets.entry(ip).or_insert(Bucket { tokens: self.rate, updated: now, rejected: 0 });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * self.rate).min(self.rate);
+        bucket.updated = now;
+
+        if bucket.tokens < 1.0 {
+            if bucket.rejected == 0 {
+                log_warn(format!("Client {} opens more than {} connections per second, rejecting its new connections", ip, self.rate));
+            }
+            bucket.rejected += 1;
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        if bucket.rejected > 0 {
+            log_recovered(ip, bucket.rejected);
+            bucket.rejected = 0;
+        }
+        true
+    }
+}
+
+fn log_recovered(ip: IpAddr, rejected: u64) {
+    log(format!("Client {} is below the connection rate again, {} connections were rejected", ip, rejected));
+}
@@ -0,0 +1,96 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use tokio::signal::unix::{signal, SignalKind};
+use crate::modules::config::{is_backend_argument, resolve_arguments};
+use crate::modules::load_balancer::{LoadBalancer, Protocol};
+use crate::modules::logging::{log, log_error, log_warn};
+use crate::modules::utils::{parse_arguments, with_protocols};
+
+// Re-read the arguments (and the config file, if any) on every SIGHUP. Static backend groups are
+// updated in place, live connections are not touched. Everything else is fixed for the lifetime
+// of the process, changes to it are reported as needing a restart.
+pub async fn reload_on_sighup(lb: Arc<LoadBalancer>, args: Vec<String>, mut current: Vec<String>, protocols: Vec<Protocol>) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            log_error(format!("Failed to install SIGHUP handler, configuration reload is disabled: {:?}", e));
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        log("Received SIGHUP, reloading configuration".to_string());
+        let reloaded = match resolve_arguments(&args) {
+            Ok(reloaded) => reloaded,
+            Err(e) => {
+                log_error(format!("Reload failed, keeping the running configuration: {}", e));
+                continue;
+            }
+        };
+        let (old_config, new_config) = match (parse_arguments(&current), parse_arguments(&reloaded)) {
+            (Ok(old_config), Ok(new_config)) => (old_config, new_config),
+            (_, Err(e)) | (Err(e), _) => {
+                log_error(format!("Reload failed, keeping the running configuration: {}", e));
+                continue;
+            }
+        };
+
+        let mut restart_needed: Vec<String> = changed_settings(&current, &reloaded);
+        if old_config.backend_max_conns != new_config.backend_max_conns {
+            restart_needed.push("backend connection limits (#n)".to_string());
+        }
+        if old_config.group_tiers != new_config.group_tiers {
+            restart_needed.push("group tiers (^n)".to_string());
+        }
+        if old_config.host_backends != new_config.host_backends {
+            restart_needed.push("host name backends".to_string());
+        }
+        if !restart_needed.is_empty() {
+            log_warn(format!("Changed settings need a restart to take effect: {}", restart_needed.join(", ")));
+        }
+
+        // Groups that disappeared are emptied, which removes them. Backends added by admin command
+        // are not part of the configuration and stay in their groups.
+        let mut new_groups = with_protocols(new_config.backends, &protocols);
+        for label in old_config.backends.keys() {
+            new_groups.entry(label.clone()).or_default();
+        }
+        for (label, mut backends) in new_groups {
+            let added = lb.admin_added_to(&label).await;
+            if !added.is_empty() {
+                let addrs: Vec<String> = added.iter().map(|backend| backend.addr.to_string()).collect();
+                log(format!("Group {}: keeping backends added by admin command: {}", label, addrs.join(", ")));
+            }
+            for backend in added {
+                if !backends.iter().any(|(addr, protocol, _)| *addr == backend.addr && *protocol == Some(backend.protocol)) {
+                    backends.push((backend.addr, Some(backend.protocol), backend.weight));
+                }
+            }
+            lb.update_dynamic_backends(&label, backends).await;
+        }
+
+        log("Configuration reloaded".to_string());
+        current = reloaded;
+    }
+}
+
+// Names of the non-backend settings whose values differ, the bind address counts as `bind`
+fn changed_settings(old: &[String], new: &[String]) -> Vec<String> {
+    let (old, new) = (settings(old), settings(new));
+    let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    keys.into_iter().filter(|key| old.get(*key) != new.get(*key)).cloned().collect()
+}
+
+// Values of every `key=value` setting that is not a backend, in the order given
+fn settings(args: &[String]) -> BTreeMap<String, Vec<String>> {
+    let mut settings: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    if let Some(bind) = args.first() {
+        settings.entry("bind".to_string()).or_default().push(bind.clone());
+    }
+    for arg in args.iter().skip(1).filter(|arg| !is_backend_argument(arg)) {
+        if let Some((key, value)) = arg.split_once('=') {
+            settings.entry(key.to_string()).or_default().push(value.to_string());
+        }
+    }
+    settings
+}
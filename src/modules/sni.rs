@@ -0,0 +1,232 @@
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::time::{timeout, Duration};
+
+const CLIENT_HELLO_TIMEOUT: Duration = Duration::from_secs(5); // Time a client gets to send its ClientHello
+const MAX_RECORD_LEN: usize = 16384 + 5; // Largest TLS plaintext record including its header
+
+const RECORD_HANDSHAKE: u8 = 0x16;
+const HANDSHAKE_CLIENT_HELLO: u8 = 0x01;
+const EXTENSION_SERVER_NAME: u16 = 0x0000;
+const NAME_TYPE_HOST: u8 = 0x00;
+
+// Outcome of parsing the start of a TLS stream
+#[derive(Debug, PartialEq)]
+pub enum ClientHello {
+    Incomplete,           // More bytes are needed
+    ServerName(String),
+    NoServerName,         // Not TLS, or a ClientHello without SNI
+}
+
+// Bounds-checked reader over the ClientHello bytes
+struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.buf.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u24(&mut self) -> Option<usize> {
+        self.take(3).map(|b| (b[0] as usize) << 16 | (b[1] as usize) << 8 | b[2] as usize)
+    }
+}
+
+// Extract the SNI host name from the first TLS record of a connection. Only ClientHellos
+// that fit into a single record are inspected, which covers every common client.
+pub fn parse_client_hello(buf: &[u8]) -> ClientHello {
+    if buf.len() < 5 {
+        return if buf.first().is_some_and(|&b| b != RECORD_HANDSHAKE) {
+            ClientHello::NoServerName
+        } else {
+            ClientHello::Incomplete
+        };
+    }
+    if buf[0] != RECORD_HANDSHAKE {
+        return ClientHello::NoServerName;
+    }
+
+    let record_len = u16::from_be_bytes([buf[3], buf[4]]) as usize;
+    if buf.len() < 5 + record_len {
+        return ClientHello::Incomplete;
+    }
+
+    server_name(&buf[5..5 + record_len]).map_or(ClientHello::NoServerName, ClientHello::ServerName)
+}
+
+fn server_name(record: &[u8]) -> Option<String> {
+    let mut hello = Cursor { buf: record, pos: 0 };
+    if hello.u8()? != HANDSHAKE_CLIENT_HELLO {
+        return None;
+    }
+    let hello_len = hello.u24()?;
+    let mut hello = Cursor { buf: hello.take(hello_len)?, pos: 0 };
+
+    hello.take(2 + 32)?; // Client version and random
+    let session_id_len = hello.u8()? as usize;
+    hello.take(session_id_len)?;
+    let cipher_suites_len = hello.u16()? as usize;
+    hello.take(cipher_suites_len)?;
+    let compression_len = hello.u8()? as usize;
+    hello.take(compression_len)?;
+
+    let extensions_len = hello.u16()? as usize;
+    let mut extensions = Cursor { buf: hello.take(extensions_len)?, pos: 0 };
+    while extensions.pos < extensions.buf.len() {
+        let extension_type = extensions.u16()?;
+        let extension_len = extensions.u16()? as usize;
+        let data = extensions.take(extension_len)?;
+        if extension_type != EXTENSION_SERVER_NAME {
+            continue;
+        }
+
+        let mut names = Cursor { buf: data, pos: 0 };
+        let list_len = names.u16()? as usize;
+        let mut names = Cursor { buf: names.take(list_len)?, pos: 0 };
+        while names.pos < names.buf.len() {
+            let name_type = names.u8()?;
+            let name_len = names.u16()? as usize;
+            let name = names.take(name_len)?;
+            if name_type == NAME_TYPE_HOST {
+                return std::str::from_utf8(name).ok().map(|name| name.to_ascii_lowercase());
+            }
+        }
+    }
+    None
+}
+
+// Read the start of the inbound stream until the SNI is known. The bytes read are returned so
+// they can be replayed to the backend, the handshake then continues untouched.
+pub async fn read_client_hello<R: AsyncRead + Unpin>(inbound: &mut R) -> (Option<String>, Vec<u8>) {
+    let mut buf = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 4096];
+
+    let read = timeout(CLIENT_HELLO_TIMEOUT, async {
+        loop {
+            match parse_client_hello(&buf) {
+                ClientHello::ServerName(name) => return Some(name),
+                ClientHello::NoServerName => return None,
+                ClientHello::Incomplete if buf.len() >= MAX_RECORD_LEN => return None,
+                ClientHello::Incomplete => {}
+            }
+            match inbound.read(&mut chunk).await {
+                Ok(0) | Err(_) => return None,
+                Ok(len) => buf.extend_from_slice(&chunk[..len]),
+            }
+        }
+    })
+    .await;
+
+    (read.unwrap_or(None), buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ClientHello of `openssl s_client -connect 127.0.0.1:9443 -servername example.com -tls1_2`
+    const OPENSSL_HELLO: &str = concat!(
+        "16030100ca010000c603034e94470c41969ea834585b3ff016f914de3d4ca1fc4542e82984df92ede4479f000036c02cc030",
+        "009fcca9cca8ccaac02bc02f009ec024c028006bc023c0270067c00ac0140039c009c0130033009d009c003d003c0035002f",
+        "01000067ff0100010000000010000e00000b6578616d706c652e636f6d000b000403000102000a000c000a001d0017001e00",
+        "1800190016000000170000000d002a0028040305030603080708080809080a080b0804080508060401050106010303030103",
+        "02040205020602",
+    );
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+    }
+
+    // A TLS record holding a ClientHello with the given extensions block (without its length)
+    fn client_hello(extensions: &[u8]) -> Vec<u8> {
+        let mut body = vec![0x03, 0x03];
+        body.extend_from_slice(&[0u8; 32]); // Random
+        body.push(0); // No session id
+        body.extend_from_slice(&[0x00, 0x02, 0x13, 0x01]); // One cipher suite
+        body.extend_from_slice(&[0x01, 0x00]); // Null compression
+        body.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        body.extend_from_slice(extensions);
+
+        let mut handshake = vec![HANDSHAKE_CLIENT_HELLO, 0, (body.len() >> 8) as u8, body.len() as u8];
+        handshake.extend_from_slice(&body);
+        let mut record = vec![RECORD_HANDSHAKE, 0x03, 0x01];
+        record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+        record.extend_from_slice(&handshake);
+        record
+    }
+
+    fn sni_extension(name: &[u8]) -> Vec<u8> {
+        let mut extension = vec![0x00, 0x00];
+        extension.extend_from_slice(&((name.len() + 5) as u16).to_be_bytes());
+        extension.extend_from_slice(&((name.len() + 3) as u16).to_be_bytes());
+        extension.push(NAME_TYPE_HOST);
+        extension.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        extension.extend_from_slice(name);
+        extension
+    }
+
+    #[test]
+    fn real_client_hello() {
+        let hello = from_hex(OPENSSL_HELLO);
+        assert_eq!(parse_client_hello(&hello), ClientHello::ServerName("example.com".to_string()));
+    }
+
+    #[test]
+    fn truncated_record_is_incomplete() {
+        let hello = from_hex(OPENSSL_HELLO);
+        for len in 0..hello.len() {
+            assert_eq!(parse_client_hello(&hello[..len]), ClientHello::Incomplete, "prefix of {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn hello_without_sni() {
+        // Only a supported_versions extension
+        let hello = client_hello(&[0x00, 0x2b, 0x00, 0x03, 0x02, 0x03, 0x04]);
+        assert_eq!(parse_client_hello(&hello), ClientHello::NoServerName);
+        assert_eq!(parse_client_hello(&client_hello(&[])), ClientHello::NoServerName);
+        assert_eq!(parse_client_hello(b"GET / HTTP/1.1\r\n"), ClientHello::NoServerName);
+    }
+
+    #[test]
+    fn built_hello_with_sni() {
+        let hello = client_hello(&sni_extension(b"Backend.Example.ORG"));
+        assert_eq!(parse_client_hello(&hello), ClientHello::ServerName("backend.example.org".to_string()));
+    }
+
+    #[test]
+    fn overflowing_lengths_do_not_panic() {
+        // Extension length past the end of the extensions block
+        assert_eq!(parse_client_hello(&client_hello(&[0x00, 0x00, 0xff, 0xff, 0x00])), ClientHello::NoServerName);
+        // Server name list length past the end of the extension
+        assert_eq!(parse_client_hello(&client_hello(&[0x00, 0x00, 0x00, 0x02, 0xff, 0xff])), ClientHello::NoServerName);
+        // Host name length past the end of the list
+        let mut extension = sni_extension(b"example.com");
+        extension[7..9].copy_from_slice(&[0xff, 0xff]);
+        assert_eq!(parse_client_hello(&client_hello(&extension)), ClientHello::NoServerName);
+
+        // Handshake length past the end of the record
+        let mut hello = client_hello(&sni_extension(b"example.com"));
+        hello[6..9].copy_from_slice(&[0xff, 0xff, 0xff]);
+        assert_eq!(parse_client_hello(&hello), ClientHello::NoServerName);
+
+        // Every byte of a real hello maxed out in turn
+        let real = from_hex(OPENSSL_HELLO);
+        for pos in 0..real.len() {
+            let mut hello = real.clone();
+            hello[pos] = 0xff;
+            parse_client_hello(&hello);
+        }
+    }
+}
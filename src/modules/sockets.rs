@@ -0,0 +1,221 @@
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpSocket, TcpStream, UdpSocket};
+use tokio::time::sleep;
+use crate::modules::logging::log_warn;
+use crate::modules::utils::unspecified_addr_for;
+use socket2::{SockRef, TcpKeepalive};
+#[cfg(target_os = "linux")]
+use socket2::{Domain, Protocol, Socket, Type};
+
+const LISTEN_BACKLOG: i32 = 1024;
+const BIND_RETRY_MAX_DELAY: Duration = Duration::from_secs(4); // Backoff cap between two bind attempts
+
+// Open `workers` TCP listeners on the same address. With more than one, SO_REUSEPORT lets the
+// kernel spread incoming connections across them, which is only available on Linux. SO_REUSEADDR
+// lets a restarted instance bind while connections of the old one linger in TIME_WAIT.
+pub async fn bind_tcp_listeners(addr: SocketAddr, workers: usize, reuse_addr: bool) -> io::Result<Vec<TcpListener>> {
+    if workers <= 1 {
+        let socket = match addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        socket.set_reuseaddr(reuse_addr)?;
+        socket.bind(addr)?;
+        return Ok(vec![socket.listen(LISTEN_BACKLOG as u32)?]);
+    }
+    (0..workers).map(|_| bind_reuseport(addr, reuse_addr)).collect()
+}
+
+// Explain a failed listener bind with its most likely cause
+pub fn bind_error_message(protocol: &str, addr: SocketAddr, error: &io::Error) -> String {
+    let cause = match error.kind() {
+        io::ErrorKind::AddrInUse => "another process (or a previous SideLB instance still shutting down) is already using it".to_string(),
+        io::ErrorKind::PermissionDenied if addr.port() < 1024 => {
+            let binary = std::env::current_exe().map(|path| path.display().to_string()).unwrap_or_else(|_| "sidelb".to_string());
+            format!(
+                "ports below 1024 need root or the CAP_NET_BIND_SERVICE capability, grant it with `setcap cap_net_bind_service=+ep {}` or use a port above 1023",
+                binary
+            )
+        }
+        io::ErrorKind::PermissionDenied => "permission denied, check security policies such as SELinux or AppArmor".to_string(),
+        io::ErrorKind::AddrNotAvailable => format!("{} is not an address of this host", addr.ip()),
+        _ => format!("{}", error),
+    };
+    format!("Failed to bind {} listener on {}: {}", protocol, addr, cause)
+}
+
+// Repeat a failed bind with exponential backoff until it succeeds or `window` has passed. Only an
+// address in use (e.g. right after a restart) or not yet assigned can clear up on its own.
+pub async fn bind_with_retry<T, F, Fut>(protocol: &str, addr: SocketAddr, window: Duration, mut bind: F) -> io::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = io::Result<T>>,
+{
+    let deadline = Instant::now() + window;
+    let mut delay = Duration::from_millis(250);
+    loop {
+        match bind().await {
+            Err(e) if matches!(e.kind(), io::ErrorKind::AddrInUse | io::ErrorKind::AddrNotAvailable) && Instant::now() < deadline => {
+                log_warn(format!("{}, retrying in {} ms", bind_error_message(protocol, addr, &e), delay.as_millis()));
+                sleep(delay.min(deadline.saturating_duration_since(Instant::now()))).await;
+                delay = (delay * 2).min(BIND_RETRY_MAX_DELAY);
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn bind_reuseport(addr: SocketAddr, reuse_addr: bool) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(reuse_addr)?;
+    socket.set_reuse_port(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+    TcpListener::from_std(socket.into())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_reuseport(_addr: SocketAddr, _reuse_addr: bool) -> io::Result<TcpListener> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "workers above 1 need SO_REUSEPORT load balancing, which requires Linux"))
+}
+
+// Apply TCP_NODELAY and keepalive to a client or backend connection. Keepalive probes start after
+// `keepalive` of silence and repeat at the same interval until the kernel gives up on the peer.
+pub fn configure_tcp_stream(stream: &TcpStream, nodelay: bool, keepalive: Option<Duration>) -> io::Result<()> {
+    if nodelay {
+        stream.set_nodelay(true)?;
+    }
+    if let Some(idle) = keepalive {
+        let keepalive = TcpKeepalive::new().with_time(idle).with_interval(idle);
+        SockRef::from(stream).set_tcp_keepalive(&keepalive)?;
+    }
+    Ok(())
+}
+
+// Local address for an outbound socket to `target`, the configured source IP or the wildcard address
+pub fn local_addr_for(target: &SocketAddr, source: Option<IpAddr>) -> SocketAddr {
+    match source {
+        Some(ip) => SocketAddr::new(ip, 0),
+        None => unspecified_addr_for(target),
+    }
+}
+
+// Connect to a backend, originating from `source` when one is configured
+pub async fn connect_tcp(addr: SocketAddr, source: Option<IpAddr>) -> io::Result<TcpStream> {
+    let Some(source) = source else {
+        return TcpStream::connect(addr).await;
+    };
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    socket.bind(SocketAddr::new(source, 0))?;
+    socket.connect(addr).await
+}
+
+// Buffers for receiving datagrams on a listener. On Linux, a batch size above 1 pulls up to that
+// many waiting datagrams with a single recvmmsg call; elsewhere one datagram is received at a time.
+pub struct UdpBatch {
+    bufs: Vec<Vec<u8>>,
+    received: Vec<(usize, SocketAddr)>,  // Length and sender of each datagram of the last receive
+}
+
+impl UdpBatch {
+    pub fn new(batch_size: usize, datagram_size: usize) -> Self {
+        UdpBatch { bufs: vec![vec![0; datagram_size]; batch_size.max(1)], received: Vec::new() }
+    }
+
+    // Wait for at least one datagram and return how many were received. Cancel safe, so it can
+    // be raced against other events in `select!`.
+    pub async fn recv(&mut self, socket: &UdpSocket) -> io::Result<usize> {
+        #[cfg(target_os = "linux")]
+        if self.bufs.len() > 1 {
+            use std::os::fd::AsRawFd;
+            use tokio::io::Interest;
+            loop {
+                socket.readable().await?;
+                match socket.try_io(Interest::READABLE, || recv_mmsg(socket.as_raw_fd(), &mut self.bufs, &mut self.received)) {
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                    result => return result,
+                }
+            }
+        }
+
+        let (len, addr) = socket.recv_from(&mut self.bufs[0]).await?;
+        self.received.clear();
+        self.received.push((len, addr));
+        Ok(1)
+    }
+
+    // Payload and sender of the `index`th datagram of the last receive
+    pub fn get(&self, index: usize) -> (&[u8], SocketAddr) {
+        let (len, addr) = self.received[index];
+        (&self.bufs[index][..len], addr)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn recv_mmsg(fd: std::os::fd::RawFd, bufs: &mut [Vec<u8>], received: &mut Vec<(usize, SocketAddr)>) -> io::Result<usize> {
+    use std::mem;
+
+    let mut addrs: Vec<libc::sockaddr_storage> = vec![unsafe { mem::zeroed() }; bufs.len()];
+    let mut iovecs: Vec<libc::iovec> = bufs
+        .iter_mut()
+        .map(|buf| libc::iovec { iov_base: buf.as_mut_ptr().cast(), iov_len: buf.len() })
+        .collect();
+    let mut msgs: Vec<libc::mmsghdr> = iovecs
+        .iter_mut()
+        .zip(addrs.iter_mut())
+        .map(|(iovec, addr)| {
+            // SAFETY: an all-zero mmsghdr is valid, the pointers set below outlive the recvmmsg call
+            let mut msg: libc::mmsghdr = unsafe { mem::zeroed() };
+            msg.msg_hdr.msg_name = (addr as *mut libc::sockaddr_storage).cast();
+            msg.msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+            msg.msg_hdr.msg_iov = iovec;
+            msg.msg_hdr.msg_iovlen = 1;
+            msg
+        })
+        .collect();
+
+    // SAFETY: `msgs` holds `msgs.len()` headers pointing into `addrs` and `bufs`, which stay alive and unmoved
+    let count = unsafe { libc::recvmmsg(fd, msgs.as_mut_ptr(), msgs.len() as libc::c_uint, libc::MSG_DONTWAIT, std::ptr::null_mut()) };
+    if count < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    received.clear();
+    for (msg, addr) in msgs.iter().zip(&addrs).take(count as usize) {
+        match sockaddr_to_socket_addr(addr) {
+            Some(addr) => received.push((msg.msg_len as usize, addr)),
+            // Keep the indices aligned with the buffers, a datagram without a usable sender is empty
+            None => received.push((0, SocketAddr::from(([0, 0, 0, 0], 0)))),
+        }
+    }
+    Ok(count as usize)
+}
+
+#[cfg(target_os = "linux")]
+fn sockaddr_to_socket_addr(addr: &libc::sockaddr_storage) -> Option<SocketAddr> {
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
+
+    match addr.ss_family as libc::c_int {
+        libc::AF_INET => {
+            // SAFETY: the family says the storage holds a sockaddr_in
+            let addr = unsafe { &*(addr as *const libc::sockaddr_storage).cast::<libc::sockaddr_in>() };
+            let ip = Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr));
+            Some(SocketAddr::V4(SocketAddrV4::new(ip, u16::from_be(addr.sin_port))))
+        }
+        libc::AF_INET6 => {
+            // SAFETY: the family says the storage holds a sockaddr_in6
+            let addr = unsafe { &*(addr as *const libc::sockaddr_storage).cast::<libc::sockaddr_in6>() };
+            let ip = Ipv6Addr::from(addr.sin6_addr.s6_addr);
+            Some(SocketAddr::V6(SocketAddrV6::new(ip, u16::from_be(addr.sin6_port), addr.sin6_flowinfo, addr.sin6_scope_id)))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,34 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+use tokio::time::Duration;
+use tokio_rustls::rustls::crypto::ring::default_provider;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+pub const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10); // Time a client gets to complete the handshake
+
+// Build the acceptor used to terminate TLS from a PEM certificate chain and private key
+pub fn load_tls_acceptor(cert_path: &str, key_path: &str) -> Result<TlsAcceptor, String> {
+    let cert_file = File::open(cert_path).map_err(|e| format!("Failed to open TLS certificate {}: {}", cert_path, e))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(cert_file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Failed to read TLS certificate {}: {}", cert_path, e))?;
+    if certs.is_empty() {
+        return Err(format!("No certificate found in {}", cert_path));
+    }
+
+    let key_file = File::open(key_path).map_err(|e| format!("Failed to open TLS key {}: {}", key_path, e))?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(key_file))
+        .map_err(|e| format!("Failed to read TLS key {}: {}", key_path, e))?
+        .ok_or_else(|| format!("No private key found in {}", key_path))?;
+
+    let config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("Failed to set up TLS: {}", e))?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("Invalid TLS certificate or key: {}", e))?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
@@ -0,0 +1,486 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::net::SocketAddr;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::Ordering;
+use serde::Serialize;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::time::{timeout, Duration};
+use crate::modules::load_balancer::{Backend, LoadBalancer, Protocol};
+use crate::modules::logging::{log, log_error, log_warn};
+
+pub const DEFAULT_UDS_PATH: &str = "/run/sidelb.sock";
+pub const DEFAULT_UDS_MODE: u32 = 0o600;  // Only the owner may query or command SideLB
+
+// Where the status socket is created and who may connect to it
+#[derive(Debug, Clone)]
+pub struct UdsConfig {
+    pub path: String,
+    pub mode: u32,  // Permission bits set on the socket file after binding
+    pub owner: Option<u32>,  // uid the socket file is handed to
+    pub group: Option<u32>,  // gid the socket file is handed to, e.g. to let a monitoring group connect
+    pub token: Option<String>,  // Shared secret that has to precede commands changing the balancer
+    pub token_reads: bool,  // Require the token for STATUS, SUMMARY and DEBUG as well
+}
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1); // Time a client has to send its command
+
+// Device and inode of the socket file this process bound, to tell it apart from one of a newer instance
+static BOUND_SOCKET: OnceLock<(u64, u64)> = OnceLock::new();
+
+#[derive(Serialize)]
+struct StatusReport {
+    healthy: bool,
+    mode: String,
+    configured_backends: usize,  // Distinct addresses over all groups, like SUMMARY
+    active_backends: usize,  // Distinct addresses currently receiving traffic
+    all_down: bool,  // No backend is active, logged as all_backends_down when it started
+    groups: Vec<GroupStatus>,
+}
+
+// Internal selection state, to check why traffic is spread the way it is
+#[derive(Serialize)]
+struct DebugReport {
+    mode: String,
+    current: BTreeMap<String, usize>,  // Round-robin and tie-break offsets
+    current_weights: BTreeMap<String, i64>,  // Smooth weighted round-robin state per backend
+    order: BTreeMap<String, Vec<String>>,  // Candidates per protocol in the order selection sees them
+    connections: BTreeMap<String, BTreeMap<String, usize>>,  // Live connections per group and backend
+}
+
+#[derive(Serialize)]
+struct GroupStatus {
+    name: String,
+    tier: u32,
+    mode: String,  // Mode picking backends within the group, the global one unless overridden
+    configured_backends: usize,
+    active_backends: usize,
+    backends: Vec<BackendStatus>,
+}
+
+#[derive(Serialize)]
+struct BackendStatus {
+    addr: String,
+    protocol: String,
+    weight: u32,
+    active: bool,
+    drained: bool,
+    removing: bool,  // Out of rotation, removed from the group once its connections finished
+    connections: usize,
+    bytes_up: u64,  // Forwarded to the backend since startup
+    bytes_down: u64,  // Forwarded from the backend since startup
+    last_healthy: Option<String>,  // Last transition to healthy, RFC 3339
+    last_unhealthy: Option<String>,  // Last transition to unhealthy, RFC 3339
+    last_error: Option<String>,  // Cause of the last failed health check, e.g. "connection refused" or "timed out"
+    circuit: String,  // Circuit breaker state: closed, open or half-open
+}
+
+// Serve the balancer state over a Unix domain socket. A client that sends nothing (or a
+// single byte) before EOF receives `HEALTHY` or `UNHEALTHY`, a `STATUS` line returns the
+// full state as JSON, `SUMMARY` the active backend counts on one line and `DEBUG` the
+// selection state. `DRAIN <addr>` and `UNDRAIN <addr>` take a backend out of rotation and
+// allow it back, `DRAIN <addr> remove` removes it once drained, `ADD <group> <addr> [proto]`
+// and `REMOVE <addr>` change the backends. With a token configured, a line `AUTH <token>`
+// has to come first for those.
+pub async fn run_uds_status_server(uds: UdsConfig, lb: Arc<LoadBalancer>) {
+    let path = uds.path.as_str();
+    if Path::new(path).exists() {
+        if let Err(e) = std::fs::remove_file(path) {
+            log_error(format!("Failed to remove stale UDS socket {}: {:?}", path, e));
+            return;
+        }
+    }
+
+    let listener = match UnixListener::bind(path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            log_error(format!("Failed to bind UDS status socket {}: {:?}", path, e));
+            return;
+        }
+    };
+    // Admin commands come in over this socket, rather serve nothing than a socket open to everyone
+    if let Err(e) = restrict_socket(&uds) {
+        log_error(format!("Failed to set permissions of UDS status socket {}: {:?}", path, e));
+        let _ = fs::remove_file(path);
+        return;
+    }
+    if let Ok(metadata) = fs::metadata(path) {
+        let _ = BOUND_SOCKET.set((metadata.dev(), metadata.ino()));
+    }
+    log(format!("UDS status server started on: {} (mode {:04o})", path, uds.mode));
+
+    let uds = Arc::new(uds);
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let lb = lb.clone();
+                let uds = uds.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve_uds_client(stream, lb, &uds).await {
+                        log_warn(format!("Failed to serve UDS client: {:?}", e));
+                    }
+                });
+            }
+            Err(e) => log_warn(format!("Failed to accept UDS connection: {:?}", e)),
+        }
+    }
+}
+
+// Remove the status socket on shutdown so its absence shows SideLB is gone. An instance started during
+// the drain has already replaced the file with its own socket, which is left alone.
+pub fn remove_uds_socket(path: &str) {
+    let Some(&bound) = BOUND_SOCKET.get() else {
+        return;
+    };
+    match fs::metadata(path) {
+        Ok(metadata) if (metadata.dev(), metadata.ino()) == bound => match fs::remove_file(path) {
+            Ok(()) => log(format!("Removed UDS status socket {}", path)),
+            Err(e) => log_warn(format!("Failed to remove UDS status socket {}: {:?}", path, e)),
+        },
+        _ => {}
+    }
+}
+
+async fn serve_uds_client(stream: UnixStream, lb: Arc<LoadBalancer>, uds: &UdsConfig) -> std::io::Result<()> {
+    let (read_half, mut write_half) = stream.into_split();
+    let mut reader = BufReader::new(read_half);
+    let mut command = String::new();
+    let _ = timeout(REQUEST_TIMEOUT, reader.read_line(&mut command)).await;
+
+    // `AUTH <token>` on the first line authenticates the command on the second
+    let mut authenticated = false;
+    if let Some(token) = command.trim().strip_prefix("AUTH ") {
+        if !uds.token.as_deref().is_some_and(|expected| tokens_match(expected, token.trim())) {
+            log_warn("Rejected UDS command with an invalid token".to_string());
+            write_half.write_all(b"ERROR invalid token\n").await?;
+            return write_half.shutdown().await;
+        }
+        authenticated = true;
+        command.clear();
+        let _ = timeout(REQUEST_TIMEOUT, reader.read_line(&mut command)).await;
+    }
+
+    let words: Vec<&str> = command.split_whitespace().collect();
+    if uds.token.is_some() && !authenticated && words.first().is_some_and(|command| needs_token(command, uds.token_reads)) {
+        log_warn(format!("Rejected unauthenticated UDS command {}", words[0]));
+        write_half.write_all(b"ERROR authentication required, send AUTH <token> first\n").await?;
+        return write_half.shutdown().await;
+    }
+
+    let response = match words.as_slice() {
+        ["STATUS"] => {
+            let report = status_report(&lb).await;
+            let mut json = serde_json::to_string(&report).unwrap_or_else(|_| "{}".to_string());
+            json.push('\n');
+            json
+        }
+        ["SUMMARY"] => summary_line(&lb).await,
+        ["DEBUG"] => {
+            let report = debug_report(&lb).await;
+            let mut json = serde_json::to_string(&report).unwrap_or_else(|_| "{}".to_string());
+            json.push('\n');
+            json
+        }
+        ["DRAIN", addr] => match addr.parse() {
+            Ok(addr) => result_line(lb.drain_backend(addr).await, format!("drained {}", addr)),
+            Err(_) => format!("ERROR invalid address {}\n", addr),
+        },
+        ["DRAIN", addr, "remove"] => match addr.parse() {
+            Ok(addr) => result_line(lb.drain_and_remove_backend(addr).await, format!("draining {}, removing it once its connections finished", addr)),
+            Err(_) => format!("ERROR invalid address {}\n", addr),
+        },
+        ["UNDRAIN", addr] => match addr.parse() {
+            Ok(addr) => result_line(lb.undrain_backend(addr).await, format!("undrained {}", addr)),
+            Err(_) => format!("ERROR invalid address {}\n", addr),
+        },
+        ["ADD", group, addr, protocol @ ..] if protocol.len() <= 1 => {
+            let protocol = match protocol.first().map(|p| p.to_lowercase()) {
+                None => Ok(None),
+                Some(p) if p == "tcp" => Ok(Some(Protocol::TCP)),
+                Some(p) if p == "udp" => Ok(Some(Protocol::UDP)),
+                Some(p) => Err(format!("ERROR invalid protocol {}, use tcp or udp\n", p)),
+            };
+            match (addr.parse(), protocol) {
+                (Ok(addr), Ok(protocol)) => match lb.add_backend(group, addr, protocol).await {
+                    Ok(members) => format!("OK {}\n", group_line(group, &members)),
+                    Err(e) => format!("ERROR {}\n", e),
+                },
+                (Err(_), _) => format!("ERROR invalid address {}\n", addr),
+                (_, Err(e)) => e,
+            }
+        }
+        ["REMOVE", addr] => match addr.parse() {
+            Ok(addr) => match lb.remove_backend(addr).await {
+                Ok(groups) => {
+                    let lines: Vec<String> = groups.iter().map(|(name, members)| group_line(name, members)).collect();
+                    format!("OK {}\n", lines.join("; "))
+                }
+                Err(e) => format!("ERROR {}\n", e),
+            },
+            Err(_) => format!("ERROR invalid address {}\n", addr),
+        },
+        [command, ..] if is_admin_command(command) => {
+            "ERROR usage: STATUS | SUMMARY | DEBUG | DRAIN <ip:port> [remove] | UNDRAIN <ip:port> | ADD <group> <ip:port> [tcp|udp] | REMOVE <ip:port>\n".to_string()
+        }
+        _ => health_line(&lb).await,
+    };
+
+    write_half.write_all(response.as_bytes()).await?;
+    write_half.shutdown().await
+}
+
+fn restrict_socket(uds: &UdsConfig) -> std::io::Result<()> {
+    if uds.owner.is_some() || uds.group.is_some() {
+        std::os::unix::fs::chown(&uds.path, uds.owner, uds.group)?;
+    }
+    fs::set_permissions(&uds.path, fs::Permissions::from_mode(uds.mode))
+}
+
+// Numeric id of a user or group name from /etc/passwd or /etc/group, whose third field is the id
+pub fn lookup_id(database: &str, name: &str) -> Option<u32> {
+    let entries = fs::read_to_string(database).ok()?;
+    entries
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.first() == Some(&name))
+        .and_then(|fields| fields.get(2)?.parse().ok())
+}
+
+// Commands that change the balancer always need the token, the read-only ones only with token_reads
+fn needs_token(command: &str, token_reads: bool) -> bool {
+    match command {
+        "DRAIN" | "UNDRAIN" | "ADD" | "REMOVE" => true,
+        "STATUS" | "SUMMARY" | "DEBUG" => token_reads,
+        _ => false,
+    }
+}
+
+// Compare without returning early, so the response time does not reveal how much of the token matched
+fn tokens_match(expected: &str, given: &str) -> bool {
+    expected.len() == given.len() && expected.bytes().zip(given.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn is_admin_command(word: &str) -> bool {
+    matches!(word, "STATUS" | "SUMMARY" | "DEBUG" | "DRAIN" | "UNDRAIN" | "ADD" | "REMOVE")
+}
+
+// `group: addr/proto, ...` listing the members of a group
+fn group_line(name: &str, members: &[Backend]) -> String {
+    let members: Vec<String> = members
+        .iter()
+        .map(|b| format!("{}/{}", b.addr, format!("{:?}", b.protocol).to_lowercase()))
+        .collect();
+    if members.is_empty() {
+        format!("{}: (removed)", name)
+    } else {
+        format!("{}: {}", name, members.join(", "))
+    }
+}
+
+// `OK <what happened>` or `ERROR <reason>`, one line for admin commands
+fn result_line(result: Result<(), String>, success: String) -> String {
+    match result {
+        Ok(()) => format!("OK {}\n", success),
+        Err(e) => format!("ERROR {}\n", e),
+    }
+}
+
+async fn health_line(lb: &LoadBalancer) -> String {
+    if lb.is_healthy().await {
+        "HEALTHY\n".to_string()
+    } else {
+        "UNHEALTHY\n".to_string()
+    }
+}
+
+// `mode=<mode> active=<n>/<m> <group>=<n>/<m> ...`, active out of configured servers overall and per group.
+// Servers are counted by address, so one listed for TCP and UDP or in several groups counts once.
+async fn summary_line(lb: &LoadBalancer) -> String {
+    let backends = lb.backends.lock().await.clone();
+    let active_backends = lb.active_backends.lock().await.clone();
+
+    let mut groups: Vec<(&String, usize, usize)> = backends
+        .iter()
+        .map(|(name, ips)| {
+            let active = active_backends.get(name).map_or(0, |active| unique_addrs(active.iter()).len());
+            (name, active, unique_addrs(ips.iter()).len())
+        })
+        .collect();
+    groups.sort();
+    let active = unique_addrs(active_backends.values().flatten()).len();
+    let configured = unique_addrs(backends.values().flatten()).len();
+
+    let mut line = format!("mode={} active={}/{}", lb.mode, active, configured);
+    for (name, active, configured) in groups {
+        line.push_str(&format!(" {}={}/{}", name, active, configured));
+    }
+    line.push('\n');
+    line
+}
+
+fn unique_addrs<'a>(backends: impl Iterator<Item = &'a Backend>) -> HashSet<SocketAddr> {
+    backends.map(|backend| backend.addr).collect()
+}
+
+async fn debug_report(lb: &LoadBalancer) -> DebugReport {
+    let current = lb.current.lock().await.clone().into_iter().collect();
+    // Named like the tie-break offsets: plain for the whole balancer, `<addr>@<group>` for a group with its own mode
+    let current_weights = lb
+        .current_weights
+        .lock()
+        .await
+        .iter()
+        .map(|((scope, addr), weight)| {
+            let key = if scope.is_empty() { addr.to_string() } else { format!("{}@{}", addr, scope) };
+            (key, *weight)
+        })
+        .collect();
+
+    let mut order = BTreeMap::new();
+    for protocol in [Protocol::TCP, Protocol::UDP] {
+        let candidates = lb.selectable_backends(protocol).await;
+        if !candidates.is_empty() {
+            order.insert(format!("{:?}", protocol), candidates.iter().map(|b| b.addr.to_string()).collect());
+        }
+    }
+
+    let backends = lb.backends.lock().await.clone();
+    let connection_counts = lb.connection_counts.lock().await.clone();
+    let connections = backends
+        .iter()
+        .map(|(name, ips)| {
+            let counts = ips
+                .iter()
+                .map(|b| (b.addr.to_string(), connection_counts.get(&b.addr).copied().unwrap_or(0)))
+                .collect();
+            (name.clone(), counts)
+        })
+        .collect();
+
+    DebugReport { mode: lb.mode.to_string(), current, current_weights, order, connections }
+}
+
+async fn status_report(lb: &LoadBalancer) -> StatusReport {
+    let backends = lb.backends.lock().await.clone();
+    let active_backends = lb.active_backends.lock().await.clone();
+    let connection_counts = lb.connection_counts.lock().await.clone();
+    let admin_drained = lb.admin_drained.lock().await.clone();
+    let removing = lb.removing.lock().await.clone();
+    let backend_bytes = lb.backend_bytes.lock().await.clone();
+    let transitions = lb.transitions.lock().await.clone();
+    let probe_errors = lb.probe_errors.lock().await.clone();
+    let circuits = lb.circuits.lock().await.clone();
+
+    let mut groups: Vec<GroupStatus> = backends
+        .iter()
+        .map(|(name, ips)| GroupStatus {
+            name: name.clone(),
+            tier: lb.group_tiers.get(name).copied().unwrap_or(0),
+            mode: lb.group_modes.get(name).copied().unwrap_or(lb.mode).to_string(),
+            configured_backends: unique_addrs(ips.iter()).len(),
+            active_backends: active_backends.get(name).map_or(0, |active| unique_addrs(active.iter()).len()),
+            backends: ips
+                .iter()
+                .map(|backend| {
+                    let (bytes_up, bytes_down) = backend_bytes.get(&backend.addr).copied().unwrap_or_default();
+                    let transition = transitions.get(&(backend.addr, backend.protocol)).copied().unwrap_or_default();
+                    BackendStatus {
+                        addr: backend.addr.to_string(),
+                        protocol: format!("{:?}", backend.protocol),
+                        weight: backend.weight,
+                        active: active_backends
+                            .get(name)
+                            .is_some_and(|active| active.iter().any(|b| b.is_same(backend))),
+                        drained: admin_drained.contains(&backend.addr),
+                        removing: removing.contains_key(&(name.clone(), backend.addr, backend.protocol)),
+                        connections: connection_counts.get(&backend.addr).copied().unwrap_or(0),
+                        bytes_up,
+                        bytes_down,
+                        last_healthy: transition.healthy.map(|at| at.to_rfc3339()),
+                        last_unhealthy: transition.unhealthy.map(|at| at.to_rfc3339()),
+                        last_error: probe_errors.get(&(backend.addr, backend.protocol)).map(|error| error.to_string()),
+                        circuit: match backend.protocol {
+                            Protocol::TCP => circuits.get(&backend.addr).map_or("closed".to_string(), |circuit| circuit.to_string()),
+                            Protocol::UDP => "closed".to_string(),
+                        },
+                    }
+                })
+                .collect(),
+        })
+        .collect();
+    groups.sort_by(|a, b| a.name.cmp(&b.name));
+
+    StatusReport {
+        healthy: lb.is_healthy().await,
+        mode: lb.mode.to_string(),
+        configured_backends: unique_addrs(backends.values().flatten()).len(),
+        active_backends: unique_addrs(active_backends.values().flatten()).len(),
+        all_down: lb.all_down.load(Ordering::SeqCst),
+        groups,
+    }
+}
+
+// Client side used as a container health check, exits 0 only if the server reports HEALTHY
+pub async fn perform_uds_health_check(path: &str, min_active: Option<usize>, token: Option<&str>) -> i32 {
+    // The plain check sends nothing, counting active backends needs the SUMMARY line,
+    // preceded by the token for a server started with uds_token_reads=true
+    let request = match (min_active, token) {
+        (None, _) => String::new(),
+        (Some(_), None) => "SUMMARY\n".to_string(),
+        (Some(_), Some(token)) => format!("AUTH {}\nSUMMARY\n", token),
+    };
+    let Some(response) = query_uds(path, &request).await else {
+        return 1;
+    };
+
+    let Some(min_active) = min_active else {
+        println!("{}", response);
+        return if response == "HEALTHY" { 0 } else { 1 };
+    };
+    let active = response
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("active="))
+        .and_then(|counts| counts.split_once('/'))
+        .and_then(|(active, configured)| Some((active.parse::<usize>().ok()?, configured.parse::<usize>().ok()?)));
+    match active {
+        Some((active, configured)) if active >= min_active && active > 0 => {
+            println!("HEALTHY {}/{} backends active", active, configured);
+            0
+        }
+        Some((active, configured)) => {
+            println!("DEGRADED {}/{} backends active, at least {} required", active, configured, min_active);
+            1
+        }
+        None => {
+            println!("{}", response);
+            1
+        }
+    }
+}
+
+// Send one request to the status socket and return the trimmed response
+async fn query_uds(path: &str, request: &str) -> Option<String> {
+    let mut stream = match UnixStream::connect(path).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            eprintln!("Failed to connect to UDS status socket {}: {:?}", path, e);
+            return None;
+        }
+    };
+
+    if stream.write_all(request.as_bytes()).await.is_err() || stream.shutdown().await.is_err() {
+        return None;
+    }
+
+    let mut response = String::new();
+    match timeout(REQUEST_TIMEOUT * 5, stream.read_to_string(&mut response)).await {
+        Ok(Ok(_)) => Some(response.trim().to_string()),
+        _ => {
+            eprintln!("No response from UDS status socket {}", path);
+            None
+        }
+    }
+}
@@ -1,11 +1,104 @@
-use chrono::Local;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::collections::HashMap;
-use crate::modules::load_balancer::{LoadBalancerMode, Protocol};
+use std::path::PathBuf;
+use std::time::Duration;
+use rand::Rng;
+use tokio::signal::unix::{signal, SignalKind};
+use crate::modules::dns::{HostBackend, IpFamily};
+use crate::modules::health::HealthCheckConfig;
+use crate::modules::load_balancer::{LoadBalancerMode, Protocol, RrScope};
+use crate::modules::logging::{log, log_warn, LogConfig};
+use crate::modules::proxy_protocol::ProxyProtocol;
+use crate::modules::uds::{lookup_id, DEFAULT_UDS_MODE, DEFAULT_UDS_PATH};
 
-pub fn log(message: String) {
-    let now = Local::now();
-    println!("[{}] {}", now.format("%Y-%m-%d %H:%M:%S"), message);
+// Static backends grouped by label (the host IP by default), each with its protocol (None follows proto=) and selection weight
+pub type BackendGroups = HashMap<String, Vec<(SocketAddr, Option<Protocol>, u32)>>;
+
+// Pin every static backend to a protocol, backends without one get an entry per listening protocol
+pub fn with_protocols(groups: BackendGroups, protocols: &[Protocol]) -> BackendGroups {
+    let mut pinned = HashMap::new();
+    for (hostname, ips) in groups {
+        let mut backend_list: Vec<(SocketAddr, Option<Protocol>, u32)> = Vec::new();
+        for (addr, protocol, weight) in ips {
+            match protocol {
+                Some(protocol) => {
+                    if !protocols.contains(&protocol) {
+                        log_warn(format!("Backend {} expects {:?} but SideLB does not listen on it, it will not receive traffic", addr, protocol));
+                    }
+                    backend_list.push((addr, Some(protocol), weight));
+                }
+                None => backend_list.extend(protocols.iter().map(|&proto| (addr, Some(proto), weight))), // One entry per listening protocol
+            }
+        }
+        pinned.insert(hostname, backend_list);
+    }
+    pinned
+}
+
+// Stretch or shrink `duration` by a random share of up to `jitter`, so instances started together
+// do not keep probing backends and querying DNS in lockstep
+pub fn jittered(duration: Duration, jitter: f64) -> Duration {
+    if jitter <= 0.0 {
+        return duration;
+    }
+    duration.mul_f64(1.0 + rand::thread_rng().gen_range(-jitter..=jitter))
+}
+
+// Bytes of a probe payload given on the command line, where `\xNN`, `\n`, `\r`, `\t`, `\0` and `\\`
+// stand for bytes that cannot be typed, e.g. the binary header of a DNS query
+pub fn unescape_bytes(value: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        let Some((&escape, tail)) = rest.split_first() else {
+            return Err(format!("Invalid escape at the end of: {}", value));
+        };
+        rest = tail;
+        match escape {
+            b'n' => bytes.push(b'\n'),
+            b'r' => bytes.push(b'\r'),
+            b't' => bytes.push(b'\t'),
+            b'0' => bytes.push(0),
+            b'\\' => bytes.push(b'\\'),
+            b'x' => {
+                let hex = rest.get(..2).filter(|hex| hex.iter().all(u8::is_ascii_hexdigit)).and_then(|hex| std::str::from_utf8(hex).ok());
+                let byte = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()).ok_or_else(|| format!("Invalid \\x escape, use two hex digits: {}", value))?;
+                bytes.push(byte);
+                rest = &rest[2..];
+            }
+            other => return Err(format!("Invalid escape \\{} in: {}", other as char, value)),
+        }
+    }
+    Ok(bytes)
+}
+
+// Inverse of unescape_bytes, printable ASCII stays as it is
+pub fn escape_bytes(bytes: &[u8]) -> String {
+    let mut escaped = String::new();
+    for &byte in bytes {
+        match byte {
+            b'\n' => escaped.push_str("\\n"),
+            b'\r' => escaped.push_str("\\r"),
+            b'\t' => escaped.push_str("\\t"),
+            b'\\' => escaped.push_str("\\\\"),
+            b' '..=b'~' => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    escaped
+}
+
+// Wildcard local address of the same family as `target`, for binding outbound sockets
+pub fn unspecified_addr_for(target: &SocketAddr) -> SocketAddr {
+    match target {
+        SocketAddr::V4(_) => SocketAddr::from(([0, 0, 0, 0], 0)),
+        SocketAddr::V6(_) => SocketAddr::from(([0u16; 8], 0)),
+    }
 }
 
 pub fn print_help() {
@@ -16,48 +109,797 @@ pub fn print_help() {
     println!("===============================");
     println!();
     println!("Usage:");
-    println!("  sidelb <bind_addr:bind_port> [backend_addr1:port] [mode=<load_balancer_mode>] [proto=<tcp|udp>] [ring_domain=<ring_domain:port>]");
+    println!("  sidelb <bind_addr:bind_port> [[label@]backend_addr1:port[/proto][*weight][#max_conns][^tier]] [backends=<[label@]addr:port[/proto][*weight][#max_conns][^tier],...>] [mode=<load_balancer_mode>] [proto=<tcp|udp|tcp,udp>] [ring_domain=<ring_domain:port>]");
+    println!("  sidelb --config <path.toml> [bind_addr:bind_port] [key=value ...]");
     println!();
     println!("Arguments:");
-    println!("  <bind_addr:bind_port>                 Address to bind the load balancer (e.g., 127.0.0.1:5432)");
-    println!("  [backend_addr1:port ...]              List of backend addresses (e.g., 127.0.0.1:8081)");
-    println!("  [mode=<load_balancer_mode>]           Load balancer mode (e.g., round-robin, least-connections). Default is round-robin.");
-    println!("  [proto=<tcp|udp>]                     Protocol to use for the load balancer choose between TCP and UDP. Default is TCP if not set.");
-    println!("  [ring_domain=<ring_domain:port>]      A hostname that resolves to multiple backend IP addresses.");
+    println!("  <bind_addr:bind_port>                 Address to bind the load balancer (e.g., 127.0.0.1:5432), or a comma-separated list of them.");
+    println!("  [backend_addr1:port[*weight] ...]     List of backend addresses (e.g., 127.0.0.1:8081 or 127.0.0.1:8081*3)");
+    println!("  [backends=<addr:port[*weight],...>]   Comma-separated backend addresses with optional weights (e.g., 10.0.0.1:80*5,10.0.0.2:80). Default weight is 1, 0 disables selection.");
+    println!("                                        A #n suffix limits the backend to n connections (e.g., 10.0.0.1:80*5#100).");
+    println!("                                        A label@ prefix puts the backend into a named group (e.g., web@10.0.0.1:80), otherwise it is grouped by IP.");
+    println!("                                        A /tcp or /udp suffix fixes the backend protocol (e.g., 10.0.0.1:53/udp), others follow proto=.");
+    println!("  [max_conns=<n>]                       Maximum connections per backend, backends with a #n suffix use their own limit. Unlimited if not set.");
+    println!("  [max_global_conns=<n>]                Maximum simultaneous TCP connections across all backends, further clients are rejected. Unlimited if not set.");
+    println!("  [conn_rate=<n>]                       Maximum new TCP connections per second from one client IP, further ones are closed. Unlimited if not set.");
+    println!("  [mode=<load_balancer_mode>]           Load balancer mode (e.g., round-robin, least-connections, weighted-least-connections, least-response-time, random, p2c, consistent-hash, maglev, adaptive). Default is round-robin.");
+    println!("  [group_mode=<group>:<mode>]           Pick backends within one group with another mode, the global mode still picks the group. May be repeated or comma-separated.");
+    println!("  [rr_scope=<global|group>]             Round-robin over all backends, or over the groups first and then within the group. Default is global.");
+    println!("  [adaptive_latency_weight=<percent>]   Share of the adaptive score given to latency, the rest goes to active connections. Default is 50.");
+    println!("  [proto=<tcp|udp|tcp,udp>]             Protocol to use for the load balancer choose between TCP, UDP or both on the same address. Default is TCP if not set.");
+    println!("  [ring_domain=<ring_domain:port>]      A hostname that resolves to multiple backend IP addresses. May be repeated or comma-separated.");
+    println!("  [ring_srv=<_service._proto.domain>]   An SRV name whose targets, ports and weights form a backend group. Lowest priority wins. May be repeated.");
+    println!("  [ip_family=<v4|v6|any>]               Address family to use from ring-domain resolution. Default is any.");
+    println!("  [ring_interval=<seconds>]             Upper bound between DNS re-resolutions of the ring domains, the record TTL is used when shorter. Default is 60.");
+    println!("  [ring_keep_last=<true|false>]         Keep the last resolved ring-domain backends while a re-resolution finds none. Default is false.");
+    println!("  [timer_jitter=<percent>]              Randomly lengthen or shorten each health-check and ring re-resolution interval by up to this share. Default is 10.");
+    println!("  [rdns=<true|false>]                   Look up and log the reverse-DNS name of every ring-domain address. Default is false.");
+    println!("  [rdns_cache_ttl=<seconds>]            Time a reverse-DNS name of a ring-domain address is reused before it is looked up again. Default is 300.");
+    println!("  [health_interval=<seconds>]           Interval between backend health checks. Default is 10.");
+    println!("  [health_timeout=<seconds>]            Timeout for a single backend health check. Default is 3.");
+    println!("  [health_concurrency=<n>]              Maximum number of health checks running in parallel. Default is 32.");
+    println!("  [health_http_path=</path>]            Check TCP backends with an HTTP GET to this path, only 2xx responses count as healthy.");
+    println!("  [health_grpc=<service>]               Check TCP backends with the gRPC health protocol, only SERVING counts as healthy. Empty for the whole server. Requires the grpc build feature.");
+    println!("  [udp_probe=<payload>]                 Send this datagram to UDP backends and count only a reply as healthy. Supports \\xNN, \\n, \\r, \\t, \\0 and \\\\.");
+    println!("  [udp_expect=<bytes>]                  Count a UDP reply as healthy only if it contains these bytes, escaped like udp_probe.");
+    println!("  [wait_for_health=<true|false>]        Accept traffic only after the first health check found an active backend. Default is false.");
+    println!("  [strict_health=<true|false>]          Start configured and resolved backends inactive until they passed rise health checks. Default is false.");
+    println!("  [rise=<n>]                            Consecutive successful health checks before a backend is marked healthy. Default is 1.");
+    println!("  [fall=<n>]                            Consecutive failed health checks before a backend is marked unhealthy. Default is 3.");
+    println!("  [passive_failures=<n>]                Eject a TCP backend after n failed forwards within passive_window, until health checks pass again. Disabled if not set.");
+    println!("  [passive_window=<seconds>]            Window in which passive failures are counted. Default is 30.");
+    println!("  [outlier_5xx_ratio=<percent>]         Eject an HTTP backend whose share of 5xx responses within outlier_window reaches this value. Only the first response of each connection is counted. Disabled if not set.");
+    println!("  [outlier_window=<seconds>]            Window in which HTTP response codes are counted for outlier detection. Default is 30.");
+    println!("  [outlier_cooldown=<seconds>]          Time an ejected outlier stays out of rotation before health checks may re-admit it. Default is 30.");
+    println!("  [circuit_failures=<n>]                Open the circuit of a TCP backend after n failed connects in a row, skipping it for circuit_cooldown. Disabled if not set.");
+    println!("  [circuit_cooldown=<seconds>]          Time an open circuit is skipped before a single trial connection may close it. Default is 10.");
+    println!("  [slow_start=<seconds>]                Ramp a backend that just became healthy up to its full weight over this time (round-robin, weighted-least-connections). Disabled if not set.");
+    println!("  [reuse_addr=<true|false>]             Set SO_REUSEADDR on the TCP listener, so a restart can bind while old connections linger. Default is true.");
+    println!("  [--bind-retry=<seconds>]              Keep retrying to bind an address that is in use or not yet available for this long. Disabled if not set. bind_retry= works too.");
+    println!("  [workers=<n>]                         Accept TCP connections with n listeners on the bind address (SO_REUSEPORT, Linux only). Default is 1.");
+    println!("  [idle_timeout=<seconds>]              Close TCP connections that forwarded no data in either direction for this long. Disabled if not set.");
+    println!("  [tcp_nodelay=<true|false>]            Disable Nagle's algorithm on client and backend connections. Default is false.");
+    println!("  [tcp_keepalive=<seconds>]             Send TCP keepalive probes on client and backend connections idle for this long. Disabled if not set.");
+    println!("  [source_addr=<ip>]                    Local IP that backend connections and health checks originate from. Chosen by the OS if not set.");
+    println!("  [proxy_protocol=<v1|v2>]              Send a PROXY protocol header with the client address to TCP backends. Disabled if not set.");
+    println!("  [sni_routing=<true|false>]            Route TLS connections to the group whose label matches the SNI host name, without terminating TLS. Default is false.");
+    println!("  [retries=<n>]                         Try up to n other backends when connecting to the selected TCP backend fails. Default is 0.");
+    println!("  [tcp_pool_size=<n>]                   Keep n unused connections per backend open ahead of time and hand them to new clients. Each is used once, never reused after a client closed it. Disabled if not set.");
+    println!("  [tcp_pool_idle=<seconds>]             Close a pooled connection that was not handed out for this long. Default is 5.");
+    println!("  [no_backend_wait=<ms>]                Hold a TCP client this long for a backend to become available instead of closing it right away. Disabled if not set.");
+    println!("  [connect_timeout=<ms>]                Time to wait for a backend connection before it counts as failed (and is retried). Default is 5000.");
+    println!("  [tls_cert=<path> tls_key=<path>]      Terminate TLS with this PEM certificate and key, backends receive plaintext. Requires the tls build feature.");
+    println!("  [udp_session_timeout=<seconds>]       Close a UDP client session (its backend and outbound socket) after this much idle time. Default is 30.");
+    println!("  [udp_max_sessions=<n>]                Maximum UDP client sessions open at once per bind address, datagrams of further clients are dropped. Unlimited if not set.");
+    println!("  [udp_batch=<n>]                       Datagrams to receive per system call (recvmmsg, Linux only). Default is 1, one datagram at a time.");
+    println!("  [udp_max_datagram=<bytes>]            Largest UDP datagram forwarded in either direction, longer ones are cut. Default is 65535.");
+    println!("  [drain_state=<path>]                  Save backends drained over UDS to this file and keep them drained after a restart. Disabled if not set.");
+    println!("  [drain_timeout=<seconds>]             Time to wait for active connections to finish on SIGTERM/SIGINT or before a removed backend is forgotten. Default is 30.");
+    println!("  [log_format=<text|json>]              Log as human readable text or as one JSON object per line. Default is text.");
+    println!("  [log_level=<error|warn|info|debug>]   Minimum severity of logged lines, per-connection events are debug. Default is info.");
+    println!("  [access_log=<true|false>]             Log client, backend, bytes, duration and close reason of every finished TCP connection. Default is false.");
+    println!("  [log_file=<path>]                     Also append log lines to this file. Disabled if not set.");
+    println!("  [log_stdout=<true|false>]             Write log lines to stdout/stderr. Default is true.");
+    println!("  [log_max_size=<bytes>]                Rotate the log file to <path>.1 once it exceeds this size. Default is 10485760 (10 MiB).");
+    println!("  [uds_path=<path>]                     Path of the UDS status socket. Default is /run/sidelb.sock.");
+    println!("  [uds_mode=<octal>]                    Permissions of the UDS status socket. Default is 0600, only its owner may connect.");
+    println!("  [uds_owner=<user|uid>]                Hand the UDS status socket to this user. Requires root.");
+    println!("  [uds_group=<group|gid>]               Hand the UDS status socket to this group, e.g. with uds_mode=0660 for a monitoring group.");
+    println!("  [uds_token=<secret>]                  Require a line AUTH <secret> before UDS commands that change backends. Disabled if not set.");
+    println!("  [uds_token_reads=<true|false>]        Require the token for STATUS, SUMMARY and DEBUG too. Default is false.");
+    println!("  [metrics_addr=<ip:port>]              Serve Prometheus metrics on http://<ip:port>/metrics. Disabled if not set.");
+    println!("  [http_health_addr=<ip:port>]          Answer http://<ip:port>/healthz with 200 HEALTHY or 503 UNHEALTHY. Disabled if not set.");
     println!();
     println!("Options:");
     println!("  -h, --help                            Display this help message and exit");
+    println!("  -V, --version                         Print the version and exit");
+    println!("  --config <path>                       Load settings from a TOML file, command-line arguments override its values");
+    println!("  --dump-config                         Print the effective settings, defaults included, as a TOML config file and exit");
+    println!("  --health-check-uds [path]             Query the UDS status socket of a running instance, exit 0 if HEALTHY");
+    println!("    [--min-active=<n>]                  With --health-check-uds, exit 1 while fewer than n backend addresses are active");
+    println!("    [--uds-token=<secret>]              With --min-active, the uds_token of a server with uds_token_reads=true (or SIDELB_UDS_TOKEN)");
     println!();
 }
 
-pub fn parse_arguments(args: &[String]) -> (SocketAddr, HashMap<String, Vec<SocketAddr>>, Option<String>, LoadBalancerMode, Protocol) {
-    if args.len() < 1 {
-        panic!("Insufficient arguments");
+// Where a backend entry points to, an address or a host name that is resolved through DNS
+enum BackendTarget {
+    Addr(SocketAddr),
+    Host(String, u16),
+}
+
+// A statically configured backend as given on the command line
+struct BackendEntry {
+    label: Option<String>,  // Explicit group, backends without one are grouped by IP (or host name)
+    target: BackendTarget,
+    protocol: Option<Protocol>,  // None follows proto=
+    weight: u32,
+    max_conns: Option<usize>,
+    tier: Option<u32>,  // Failover tier of the entry's group
+}
+
+// Parse a backend entry of the form `[label@]addr:port[/proto][*weight][#max_conns][^tier]`, the suffixes
+// may come in any order and `addr` may be a host name. Returns None (after a warning) for an unknown protocol suffix.
+fn parse_backend(entry: &str) -> Result<Option<BackendEntry>, String> {
+    let entry = entry.trim();
+    let (label, entry) = match entry.split_once('@') {
+        Some((label, _)) if label.trim().is_empty() => return Err(format!("Empty group label in backend {}", entry)),
+        Some((label, rest)) => (Some(label.trim().to_string()), rest.trim()),
+        None => (None, entry),
+    };
+    let addr_end = entry.find(['/', '*', '#', '^']).unwrap_or(entry.len());
+    let target = match entry[..addr_end].trim().parse() {
+        Ok(addr) => BackendTarget::Addr(addr),
+        Err(_) => parse_host_port(entry[..addr_end].trim()).ok_or_else(|| format!("Invalid backend address: {}", entry))?,
+    };
+
+    let (mut protocol, mut weight, mut max_conns, mut tier) = (None, 1, None, None);
+    let mut rest = &entry[addr_end..];
+    while let Some(marker) = rest.chars().next() {
+        let value_end = rest[1..].find(['/', '*', '#', '^']).map_or(rest.len(), |i| i + 1);
+        let value = rest[1..value_end].trim();
+        match marker {
+            '/' => match value.to_lowercase().as_str() {
+                "tcp" => protocol = Some(Protocol::TCP),
+                "udp" => protocol = Some(Protocol::UDP),
+                _ => {
+                    log_warn(format!("Skipping backend {}: unknown protocol suffix /{}, use /tcp or /udp", entry, value));
+                    return Ok(None);
+                }
+            },
+            '*' => weight = value.parse().map_err(|_| format!("Invalid backend weight in {}", entry))?,
+            '^' => tier = Some(value.parse().map_err(|_| format!("Invalid backend tier in {}", entry))?),
+            _ => max_conns = Some(value.parse().map_err(|_| format!("Invalid backend connection limit in {}", entry))?),
+        }
+        rest = &rest[value_end..];
+    }
+    Ok(Some(BackendEntry { label, target, protocol, weight, max_conns, tier }))
+}
+
+// `host:port` with a DNS host name. Something that only looks like a broken IP address (digits and dots,
+// or an IPv6 address without brackets) is not taken for a host name.
+fn parse_host_port(value: &str) -> Option<BackendTarget> {
+    let (host, port) = value.rsplit_once(':')?;
+    let port = port.parse().ok()?;
+    let valid = host.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+        && host.chars().any(|c| c.is_ascii_alphabetic())
+        && !host.starts_with(['.', '-']);
+    valid.then(|| BackendTarget::Host(host.trim_end_matches('.').to_string(), port))
+}
+
+// Settings parsed from the command line
+pub struct Config {
+    pub bind_addrs: Vec<SocketAddr>,  // Every address gets its own listeners, all feeding the same balancer
+    pub backends: BackendGroups,
+    pub host_backends: HashMap<String, Vec<HostBackend>>,  // Static backends given by host name per group, re-resolved like ring domains
+    pub ring_domains: Vec<String>,  // Domains resolved (and periodically re-resolved) into backend groups
+    pub ring_srvs: Vec<String>,  // SRV names whose targets (port, weight, priority) form backend groups
+    pub ring_interval: Duration,  // Time between two re-resolutions of each ring domain
+    pub ring_keep_last: bool,  // Ride out DNS outages with the previously resolved ring members
+    pub timer_jitter: f64,  // Random share by which ring and health-check intervals vary
+    pub rdns: bool,  // Look up reverse-DNS names of ring-domain addresses for the log
+    pub rdns_cache_ttl: Duration,  // Time a cached reverse-DNS answer stays valid
+    pub ip_family: IpFamily,  // Address families kept from ring-domain resolution
+    pub mode: LoadBalancerMode,
+    pub protocols: Vec<Protocol>,  // Protocols to listen on, backends are registered once per protocol
+    pub idle_timeout: Option<Duration>,  // Close TCP connections without traffic in either direction for this long
+    pub proxy_protocol: Option<ProxyProtocol>,  // PROXY protocol header sent to TCP backends
+    pub sni_routing: bool,  // Pick the TCP backend group by the TLS SNI host name
+    pub retries: u32,  // Other backends tried when connecting to the selected TCP backend fails
+    pub connect_timeout: Duration,  // Time a backend connect may take before it counts as failed
+    pub no_backend_wait: Option<Duration>,  // Time a TCP client may wait for an available backend
+    pub tcp_pool_size: Option<usize>,  // Pre-connected idle connections kept per backend
+    pub tcp_pool_idle: Duration,  // Lifetime of an unused pooled connection
+    pub access_log: bool,  // Log a summary line for every finished TCP connection
+    pub tls_cert: Option<String>,  // PEM certificate chain for TLS termination
+    pub tls_key: Option<String>,  // PEM private key for TLS termination
+    pub udp_session_timeout: Duration,  // Idle time after which a UDP client session is closed
+    pub udp_batch: usize,  // Datagrams received per recvmmsg call
+    pub udp_max_datagram: usize,  // Receive buffer size of each datagram, per batch slot and per session
+    pub udp_max_sessions: Option<usize>,  // Limit of simultaneous UDP sessions and their outbound sockets
+    pub health: HealthCheckConfig,
+    pub drain_timeout: Duration,  // Time to wait for active connections on shutdown
+    pub drain_state: Option<PathBuf>,  // File that keeps admin drains across restarts
+    pub uds_path: String,  // Unix domain socket serving status and admin commands
+    pub uds_mode: u32,  // Permission bits of the socket file
+    pub uds_owner: Option<u32>,  // uid and gid the socket file is handed to
+    pub uds_group: Option<u32>,
+    pub uds_token: Option<String>,  // Shared secret for mutating UDS commands
+    pub uds_token_reads: bool,  // Read-only UDS commands need the token as well
+    pub metrics_addr: Option<SocketAddr>,  // Address of the optional Prometheus endpoint
+    pub http_health_addr: Option<SocketAddr>,  // Address of the optional HTTP health endpoint
+    pub log: LogConfig,
+    pub max_conns: Option<usize>,  // Connection limit for every backend without its own limit
+    pub backend_max_conns: HashMap<SocketAddr, usize>,  // Per-backend connection limits from the `#n` suffix
+    pub group_tiers: HashMap<String, u32>,  // Failover tier per group from the `^n` suffix, 0 if not set
+    pub group_modes: HashMap<String, LoadBalancerMode>,  // Modes of groups that override the global one
+    pub rr_scope: RrScope,  // Round-robin over all backends or over the groups first
+    pub max_global_conns: Option<usize>,  // Limit of simultaneous TCP connections across the whole balancer
+    pub conn_rate: Option<u32>,  // New TCP connections a client IP may open per second
+    pub slow_start: Option<Duration>,  // Ramp-up time for backends that just became healthy
+    pub workers: usize,  // TCP listeners sharing the bind address through SO_REUSEPORT
+    pub bind_retry: Duration,  // Time to keep retrying a bind that failed with a transient error
+    pub reuse_addr: bool,  // SO_REUSEADDR on the TCP listeners
+    pub wait_for_health: bool,  // Start the listeners only once a health check found an active backend
+    pub strict_health: bool,  // Backends join the rotation only after passing health checks
+    pub tcp_nodelay: bool,  // Set TCP_NODELAY on client and backend sockets
+    pub tcp_keepalive: Option<Duration>,  // Keepalive idle time for client and backend sockets
+    pub source_addr: Option<IpAddr>,  // Local IP to originate backend connections and health checks from
+    pub adaptive_latency_weight: f64,  // Latency share of the adaptive score, 0.0 to 1.0
+}
+
+// Group a backend by its label, or by its IP (or host name) when it has none. A tier applies to the whole group.
+fn add_static_backend(
+    groups: &mut BackendGroups,
+    host_groups: &mut HashMap<String, Vec<HostBackend>>,
+    max_conns: &mut HashMap<SocketAddr, usize>,
+    tiers: &mut HashMap<String, u32>,
+    backend: BackendEntry,
+) -> Result<(), String> {
+    let group = match (&backend.label, &backend.target) {
+        (Some(label), _) => label.clone(),
+        (None, BackendTarget::Addr(addr)) => addr.ip().to_string(),
+        (None, BackendTarget::Host(host, _)) => host.clone(),
+    };
+    if let Some(tier) = backend.tier {
+        match tiers.insert(group.clone(), tier) {
+            Some(previous) if previous != tier => {
+                return Err(format!("Conflicting tiers for group {}: ^{} and ^{}", group, previous, tier));
+            }
+            _ => {}
+        }
+    }
+    let addr = match backend.target {
+        BackendTarget::Addr(addr) => addr,
+        BackendTarget::Host(host, port) => {
+            // Limits are kept per address, which a host name only has once it is resolved
+            if backend.max_conns.is_some() {
+                return Err(format!("Connection limits (#n) need an IP address, not the host name {}", host));
+            }
+            let entry = HostBackend { host, port, protocol: backend.protocol, weight: backend.weight };
+            host_groups.entry(group).or_default().push(entry);
+            return Ok(());
+        }
+    };
+    groups.entry(group).or_default().push((addr, backend.protocol, backend.weight));
+    if let Some(limit) = backend.max_conns {
+        max_conns.insert(addr, limit);
     }
+    Ok(())
+}
 
-    let bind_addr: SocketAddr = args[0].parse().expect("Invalid bind address");
-    let mut backend_groups: HashMap<String, Vec<SocketAddr>> = HashMap::new();
-    let mut ring_domain: Option<String> = None;
+// Parse the `key=value` arguments, describing the first invalid one on error
+pub fn parse_arguments(args: &[String]) -> Result<Config, String> {
+    let Some(bind_arg) = args.first().filter(|arg| !arg.contains('=')) else {
+        return Err("Missing bind address, the first argument must be <bind_addr:bind_port>".to_string());
+    };
+    let mut bind_addrs: Vec<SocketAddr> = Vec::new();
+    for entry in bind_arg.split(',') {
+        let bind_addr: SocketAddr = entry.trim().parse().map_err(|_| format!("Invalid bind address: {}", entry))?;
+        if bind_addrs.contains(&bind_addr) {
+            return Err(format!("Duplicate bind address: {}", bind_addr));
+        }
+        bind_addrs.push(bind_addr);
+    }
+    let mut backend_groups: BackendGroups = HashMap::new();
+    let mut host_groups: HashMap<String, Vec<HostBackend>> = HashMap::new();
+    let mut ring_domains: Vec<String> = Vec::new();
+    let mut ring_srvs: Vec<String> = Vec::new();
+    let mut ring_interval = Duration::from_secs(60);
+    let mut ring_keep_last = false;
+    let mut rdns = false;
+    let mut rdns_cache_ttl = Duration::from_secs(300);
+    let mut ip_family = IpFamily::Any;
     let mut mode = LoadBalancerMode::RoundRobin;
-    let mut proto = Protocol::TCP; // Default to TCP
+    let mut protocols = vec![Protocol::TCP]; // Default to TCP
+    let mut idle_timeout: Option<Duration> = None;
+    let mut proxy_protocol: Option<ProxyProtocol> = None;
+    let mut sni_routing = false;
+    let mut retries = 0;
+    let mut connect_timeout = Duration::from_millis(5000);
+    let mut no_backend_wait: Option<Duration> = None;
+    let mut tcp_pool_size: Option<usize> = None;
+    let mut tcp_pool_idle = Duration::from_secs(5);
+    let mut access_log = false;
+    let mut tls_cert: Option<String> = None;
+    let mut tls_key: Option<String> = None;
+    let mut udp_session_timeout = Duration::from_secs(30);
+    let mut udp_max_sessions: Option<usize> = None;
+    let mut udp_batch: usize = 1;
+    let mut udp_max_datagram: usize = 65535;
+    let mut health = HealthCheckConfig::default();
+    let mut timer_jitter = 0.1;
+    let mut drain_timeout = Duration::from_secs(30);
+    let mut drain_state: Option<PathBuf> = None;
+    let mut uds_path = DEFAULT_UDS_PATH.to_string();
+    let mut uds_mode = DEFAULT_UDS_MODE;
+    let mut uds_owner: Option<u32> = None;
+    let mut uds_group: Option<u32> = None;
+    let mut uds_token: Option<String> = None;
+    let mut uds_token_reads = false;
+    let mut metrics_addr: Option<SocketAddr> = None;
+    let mut http_health_addr: Option<SocketAddr> = None;
+    let mut log_config = LogConfig::default();
+    let mut max_conns: Option<usize> = None;
+    let mut backend_max_conns: HashMap<SocketAddr, usize> = HashMap::new();
+    let mut group_tiers: HashMap<String, u32> = HashMap::new();
+    let mut group_modes: HashMap<String, LoadBalancerMode> = HashMap::new();
+    let mut rr_scope = RrScope::Global;
+    let mut max_global_conns: Option<usize> = None;
+    let mut conn_rate: Option<u32> = None;
+    let mut slow_start: Option<Duration> = None;
+    let mut workers = 1;
+    let mut bind_retry = Duration::ZERO;
+    let mut reuse_addr = true;
+    let mut wait_for_health = false;
+    let mut strict_health = false;
+    let mut tcp_nodelay = false;
+    let mut tcp_keepalive: Option<Duration> = None;
+    let mut source_addr: Option<IpAddr> = None;
+    let mut adaptive_latency_weight = 0.5;
 
     for arg in &args[1..] {
-        if arg.starts_with("ring_domain=") {
-            ring_domain = Some(arg["ring_domain=".len()..].to_string());
-        } else if arg.starts_with("mode=") {
-            mode = arg["mode=".len()..].parse().expect("Invalid load balancer mode");
-        } else if arg.starts_with("proto=") {
-            proto = match arg["proto=".len()..].to_lowercase().as_str() {
-                "udp" => Protocol::UDP,
-                "tcp" => Protocol::TCP,
-                _ => panic!("Invalid protocol"),
-            };
-        } else {
-            let addr: SocketAddr = arg.parse().expect("Invalid backend address");
-            let host = addr.ip().to_string();
-            backend_groups.entry(host).or_insert_with(Vec::new).push(addr);
-        }
-    }
-
-    (bind_addr, backend_groups, ring_domain, mode, proto)
+        if let Some(value) = arg.strip_prefix("ring_domain=") {
+            for domain in value.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+                match domain.rsplit_once(':') {
+                    Some((_, port)) if port.parse::<u16>().is_ok() => {}
+                    Some((_, port)) => return Err(format!("Invalid port in ring_domain {}: {}", domain, port)),
+                    None => return Err(format!("Missing port in ring_domain {}, use <domain:port>", domain)),
+                }
+                if !ring_domains.iter().any(|d| d == domain) {
+                    ring_domains.push(domain.to_string());
+                }
+            }
+        } else if let Some(value) = arg.strip_prefix("ring_srv=") {
+            for name in value.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+                if !ring_srvs.iter().any(|n| n == name) {
+                    ring_srvs.push(name.to_string());
+                }
+            }
+        } else if let Some(value) = arg.strip_prefix("ring_interval=") {
+            let seconds: u64 = value.parse().map_err(|_| format!("Invalid ring re-resolution interval: {}", value))?;
+            if seconds == 0 {
+                return Err("Invalid ring re-resolution interval: must be at least 1 second".to_string());
+            }
+            ring_interval = Duration::from_secs(seconds);
+        } else if let Some(value) = arg.strip_prefix("ring_keep_last=") {
+            ring_keep_last = value.parse().map_err(|_| format!("Invalid ring_keep_last value, use true or false: {}", value))?;
+        } else if let Some(value) = arg.strip_prefix("rdns=") {
+            rdns = value.parse().map_err(|_| format!("Invalid rdns value, use true or false: {}", value))?;
+        } else if let Some(value) = arg.strip_prefix("rdns_cache_ttl=") {
+            rdns_cache_ttl = Duration::from_secs(value.parse().map_err(|_| format!("Invalid reverse-DNS cache TTL: {}", value))?);
+        } else if let Some(value) = arg.strip_prefix("ip_family=") {
+            ip_family = value.parse().map_err(|_| format!("Invalid IP family, use v4, v6 or any: {}", value))?;
+        } else if let Some(value) = arg.strip_prefix("mode=") {
+            mode = value.parse().map_err(|_| format!("Invalid load balancer mode: {}", value))?;
+        } else if let Some(value) = arg.strip_prefix("group_mode=") {
+            // Split at the last colon, ring-domain group labels carry a port
+            for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+                let Some((group, group_mode)) = entry.rsplit_once(':').filter(|(group, _)| !group.is_empty()) else {
+                    return Err(format!("Invalid group mode, use <group>:<mode>: {}", entry));
+                };
+                let group_mode = group_mode.parse().map_err(|_| format!("Invalid load balancer mode for group {}: {}", group, group_mode))?;
+                group_modes.insert(group.to_string(), group_mode);
+            }
+        } else if let Some(value) = arg.strip_prefix("rr_scope=") {
+            rr_scope = value.parse().map_err(|_| format!("Invalid rr_scope, use global or group: {}", value))?;
+        } else if let Some(value) = arg.strip_prefix("proto=") {
+            protocols.clear();
+            for name in value.split(',') {
+                let protocol = match name.trim().to_lowercase().as_str() {
+                    "udp" => Protocol::UDP,
+                    "tcp" => Protocol::TCP,
+                    _ => return Err(format!("Invalid protocol: {}, use tcp, udp or tcp,udp", name.trim())),
+                };
+                if !protocols.contains(&protocol) {
+                    protocols.push(protocol);
+                }
+            }
+        } else if let Some(value) = arg.strip_prefix("idle_timeout=") {
+            let seconds: u64 = value.parse().map_err(|_| format!("Invalid idle timeout: {}", value))?;
+            if seconds == 0 {
+                return Err("Invalid idle timeout: must be at least 1 second".to_string());
+            }
+            idle_timeout = Some(Duration::from_secs(seconds));
+        } else if let Some(value) = arg.strip_prefix("proxy_protocol=") {
+            proxy_protocol = Some(value.parse().map_err(|_| format!("Invalid PROXY protocol version, use v1 or v2: {}", value))?);
+        } else if let Some(value) = arg.strip_prefix("sni_routing=") {
+            sni_routing = value.parse().map_err(|_| format!("Invalid sni_routing value, use true or false: {}", value))?;
+        } else if let Some(value) = arg.strip_prefix("retries=") {
+            retries = value.parse().map_err(|_| format!("Invalid retry count: {}", value))?;
+        } else if let Some(value) = arg.strip_prefix("tcp_pool_size=") {
+            let size: usize = value.parse().map_err(|_| format!("Invalid TCP pool size: {}", value))?;
+            tcp_pool_size = Some(size).filter(|size| *size > 0);
+        } else if let Some(value) = arg.strip_prefix("tcp_pool_idle=") {
+            let seconds: u64 = value.parse().map_err(|_| format!("Invalid TCP pool idle timeout: {}", value))?;
+            if seconds == 0 {
+                return Err("Invalid TCP pool idle timeout: must be at least 1 second".to_string());
+            }
+            tcp_pool_idle = Duration::from_secs(seconds);
+        } else if let Some(value) = arg.strip_prefix("connect_timeout=") {
+            let millis: u64 = value.parse().map_err(|_| format!("Invalid connect timeout: {}", value))?;
+            if millis == 0 {
+                return Err("Invalid connect timeout: must be at least 1 ms".to_string());
+            }
+            connect_timeout = Duration::from_millis(millis);
+        } else if let Some(value) = arg.strip_prefix("no_backend_wait=") {
+            let millis: u64 = value.parse().map_err(|_| format!("Invalid no_backend_wait: {}", value))?;
+            no_backend_wait = Some(Duration::from_millis(millis)).filter(|wait| !wait.is_zero());
+        } else if let Some(value) = arg.strip_prefix("access_log=") {
+            access_log = value.parse().map_err(|_| format!("Invalid access_log value, use true or false: {}", value))?;
+        } else if let Some(value) = arg.strip_prefix("tls_cert=") {
+            tls_cert = Some(value.to_string());
+        } else if let Some(value) = arg.strip_prefix("tls_key=") {
+            tls_key = Some(value.to_string());
+        } else if let Some(value) = arg.strip_prefix("udp_max_sessions=") {
+            udp_max_sessions = Some(value.parse().map_err(|_| format!("Invalid UDP session limit: {}", value))?);
+        } else if let Some(value) = arg.strip_prefix("udp_batch=") {
+            udp_batch = value.parse().map_err(|_| format!("Invalid UDP batch size: {}", value))?;
+            if !(1..=1024).contains(&udp_batch) {
+                return Err("Invalid UDP batch size: must be between 1 and 1024".to_string());
+            }
+        } else if let Some(value) = arg.strip_prefix("udp_max_datagram=") {
+            udp_max_datagram = value.parse().map_err(|_| format!("Invalid UDP datagram size: {}", value))?;
+            if !(512..=65535).contains(&udp_max_datagram) {
+                return Err("Invalid UDP datagram size: must be between 512 and 65535 bytes".to_string());
+            }
+        } else if let Some(value) = arg.strip_prefix("udp_session_timeout=") {
+            udp_session_timeout = Duration::from_secs(value.parse().map_err(|_| format!("Invalid UDP session timeout: {}", value))?);
+        } else if let Some((key, _)) = arg.split_once('=').filter(|(key, _)| matches!(*key, "udp_response_timeout" | "udp_response_idle_ms")) {
+            // Replaced by UDP sessions, which forward responses until the session goes idle
+            log_warn(format!("{} is deprecated and ignored, UDP responses are forwarded until udp_session_timeout closes the idle session", key));
+        } else if let Some(value) = arg.strip_prefix("health_interval=") {
+            let seconds: u64 = value.parse().map_err(|_| format!("Invalid health check interval: {}", value))?;
+            if seconds == 0 {
+                return Err("Invalid health check interval: must be at least 1 second".to_string());
+            }
+            health.interval = Duration::from_secs(seconds);
+        } else if let Some(value) = arg.strip_prefix("health_timeout=") {
+            let seconds: u64 = value.parse().map_err(|_| format!("Invalid health check timeout: {}", value))?;
+            if seconds == 0 {
+                return Err("Invalid health check timeout: must be at least 1 second".to_string());
+            }
+            health.timeout = Duration::from_secs(seconds);
+        } else if let Some(value) = arg.strip_prefix("health_concurrency=") {
+            let concurrency: usize = value.parse().map_err(|_| format!("Invalid health check concurrency: {}", value))?;
+            if concurrency == 0 {
+                return Err("Invalid health check concurrency: must be at least 1".to_string());
+            }
+            health.concurrency = concurrency;
+        } else if let Some(value) = arg.strip_prefix("health_http_path=") {
+            if !value.starts_with('/') {
+                return Err("Invalid health check HTTP path: must start with '/'".to_string());
+            }
+            health.http_path = Some(value.to_string());
+        } else if let Some(value) = arg.strip_prefix("health_grpc=") {
+            health.grpc_service = Some(value.to_string());
+        } else if let Some(value) = arg.strip_prefix("udp_probe=") {
+            health.udp_payload = Some(unescape_bytes(value)?);
+        } else if let Some(value) = arg.strip_prefix("udp_expect=") {
+            let expect = unescape_bytes(value)?;
+            if expect.is_empty() {
+                return Err("Invalid expected UDP reply: must not be empty".to_string());
+            }
+            health.udp_expect = Some(expect);
+        } else if let Some(value) = arg.strip_prefix("rise=") {
+            health.rise = value.parse().map_err(|_| format!("Invalid rise threshold: {}", value))?;
+            if health.rise == 0 {
+                return Err("Invalid rise threshold: must be at least 1".to_string());
+            }
+        } else if let Some(value) = arg.strip_prefix("fall=") {
+            health.fall = value.parse().map_err(|_| format!("Invalid fall threshold: {}", value))?;
+            if health.fall == 0 {
+                return Err("Invalid fall threshold: must be at least 1".to_string());
+            }
+        } else if let Some(value) = arg.strip_prefix("passive_failures=") {
+            health.passive_failures = value.parse().map_err(|_| format!("Invalid passive failure threshold: {}", value))?;
+        } else if let Some(value) = arg.strip_prefix("passive_window=") {
+            let seconds: u64 = value.parse().map_err(|_| format!("Invalid passive failure window: {}", value))?;
+            if seconds == 0 {
+                return Err("Invalid passive failure window: must be at least 1 second".to_string());
+            }
+            health.passive_window = Duration::from_secs(seconds);
+        } else if let Some(value) = arg.strip_prefix("outlier_5xx_ratio=") {
+            let percent: u8 = value.parse().map_err(|_| format!("Invalid outlier 5xx ratio: {}", value))?;
+            if percent == 0 || percent > 100 {
+                return Err("Invalid outlier 5xx ratio: must be between 1 and 100".to_string());
+            }
+            health.outlier_5xx_ratio = percent as f64 / 100.0;
+        } else if let Some(value) = arg.strip_prefix("outlier_window=") {
+            let seconds: u64 = value.parse().map_err(|_| format!("Invalid outlier window: {}", value))?;
+            if seconds == 0 {
+                return Err("Invalid outlier window: must be at least 1 second".to_string());
+            }
+            health.outlier_window = Duration::from_secs(seconds);
+        } else if let Some(value) = arg.strip_prefix("outlier_cooldown=") {
+            health.outlier_cooldown = Duration::from_secs(value.parse().map_err(|_| format!("Invalid outlier cooldown: {}", value))?);
+        } else if let Some(value) = arg.strip_prefix("circuit_failures=") {
+            health.circuit_failures = value.parse().map_err(|_| format!("Invalid circuit failure threshold: {}", value))?;
+        } else if let Some(value) = arg.strip_prefix("circuit_cooldown=") {
+            health.circuit_cooldown = Duration::from_secs(value.parse().map_err(|_| format!("Invalid circuit cooldown: {}", value))?);
+        } else if let Some(value) = arg.strip_prefix("drain_state=") {
+            drain_state = Some(PathBuf::from(value));
+        } else if let Some(value) = arg.strip_prefix("drain_timeout=") {
+            drain_timeout = Duration::from_secs(value.parse().map_err(|_| format!("Invalid drain timeout: {}", value))?);
+        } else if let Some(value) = arg.strip_prefix("uds_path=") {
+            if value.is_empty() {
+                return Err("Invalid UDS path: must not be empty".to_string());
+            }
+            uds_path = value.to_string();
+        } else if let Some(value) = arg.strip_prefix("uds_mode=") {
+            uds_mode = u32::from_str_radix(value, 8)
+                .ok()
+                .filter(|mode| *mode <= 0o777)
+                .ok_or_else(|| format!("Invalid UDS mode, use octal permissions like 0660: {}", value))?;
+        } else if let Some(value) = arg.strip_prefix("uds_owner=") {
+            uds_owner = Some(value.parse().ok().or_else(|| lookup_id("/etc/passwd", value)).ok_or_else(|| format!("Unknown user: {}", value))?);
+        } else if let Some(value) = arg.strip_prefix("uds_group=") {
+            uds_group = Some(value.parse().ok().or_else(|| lookup_id("/etc/group", value)).ok_or_else(|| format!("Unknown group: {}", value))?);
+        } else if let Some(value) = arg.strip_prefix("uds_token=") {
+            if value.is_empty() || value.contains(char::is_whitespace) {
+                return Err("Invalid UDS token: must not be empty or contain whitespace".to_string());
+            }
+            uds_token = Some(value.to_string());
+        } else if let Some(value) = arg.strip_prefix("uds_token_reads=") {
+            uds_token_reads = value.parse().map_err(|_| format!("Invalid uds_token_reads value, use true or false: {}", value))?;
+        } else if let Some(value) = arg.strip_prefix("metrics_addr=") {
+            metrics_addr = Some(value.parse().map_err(|_| format!("Invalid metrics address: {}", value))?);
+        } else if let Some(value) = arg.strip_prefix("http_health_addr=") {
+            http_health_addr = Some(value.parse().map_err(|_| format!("Invalid HTTP health address: {}", value))?);
+        } else if let Some(value) = arg.strip_prefix("log_format=") {
+            log_config.format = value.parse().map_err(|_| format!("Invalid log format, use text or json: {}", value))?;
+        } else if let Some(value) = arg.strip_prefix("log_level=") {
+            log_config.level = value.parse().map_err(|_| format!("Invalid log level, use error, warn, info or debug: {}", value))?;
+        } else if let Some(value) = arg.strip_prefix("log_file=") {
+            log_config.file = Some(value.to_string());
+        } else if let Some(value) = arg.strip_prefix("log_stdout=") {
+            log_config.stdout = value.parse().map_err(|_| format!("Invalid log_stdout value, use true or false: {}", value))?;
+        } else if let Some(value) = arg.strip_prefix("log_max_size=") {
+            log_config.max_size = value.parse().map_err(|_| format!("Invalid log file size: {}", value))?;
+            if log_config.max_size == 0 {
+                return Err("Invalid log file size: must be at least 1 byte".to_string());
+            }
+        } else if let Some(value) = arg.strip_prefix("max_conns=") {
+            max_conns = Some(value.parse().map_err(|_| format!("Invalid connection limit: {}", value))?);
+        } else if let Some(value) = arg.strip_prefix("wait_for_health=") {
+            wait_for_health = value.parse().map_err(|_| format!("Invalid wait_for_health value, use true or false: {}", value))?;
+        } else if let Some(value) = arg.strip_prefix("strict_health=") {
+            strict_health = value.parse().map_err(|_| format!("Invalid strict_health value, use true or false: {}", value))?;
+        } else if let Some(value) = arg.strip_prefix("reuse_addr=") {
+            reuse_addr = value.parse().map_err(|_| format!("Invalid reuse_addr value, use true or false: {}", value))?;
+        } else if let Some(value) = arg.strip_prefix("--bind-retry=").or_else(|| arg.strip_prefix("bind_retry=")) {
+            bind_retry = Duration::from_secs(value.parse().map_err(|_| format!("Invalid bind retry time: {}", value))?);
+        } else if let Some(value) = arg.strip_prefix("workers=") {
+            workers = value.parse().map_err(|_| format!("Invalid worker count: {}", value))?;
+            if workers == 0 {
+                return Err("Invalid worker count: must be at least 1".to_string());
+            }
+        } else if let Some(value) = arg.strip_prefix("tcp_nodelay=") {
+            tcp_nodelay = value.parse().map_err(|_| format!("Invalid tcp_nodelay value, use true or false: {}", value))?;
+        } else if let Some(value) = arg.strip_prefix("tcp_keepalive=") {
+            let seconds: u64 = value.parse().map_err(|_| format!("Invalid TCP keepalive time: {}", value))?;
+            if seconds == 0 {
+                return Err("Invalid TCP keepalive time: must be at least 1 second".to_string());
+            }
+            tcp_keepalive = Some(Duration::from_secs(seconds));
+        } else if let Some(value) = arg.strip_prefix("timer_jitter=") {
+            let percent: u8 = value.parse().map_err(|_| format!("Invalid timer jitter: {}", value))?;
+            if percent > 50 {
+                return Err("Invalid timer jitter: must be between 0 and 50".to_string());
+            }
+            timer_jitter = percent as f64 / 100.0;
+        } else if let Some(value) = arg.strip_prefix("adaptive_latency_weight=") {
+            let percent: u8 = value.parse().map_err(|_| format!("Invalid adaptive latency weight: {}", value))?;
+            if percent > 100 {
+                return Err("Invalid adaptive latency weight: must be between 0 and 100".to_string());
+            }
+            adaptive_latency_weight = percent as f64 / 100.0;
+        } else if let Some(value) = arg.strip_prefix("source_addr=") {
+            source_addr = Some(value.parse().map_err(|_| format!("Invalid source address: {}", value))?);
+        } else if let Some(value) = arg.strip_prefix("slow_start=") {
+            let seconds: u64 = value.parse().map_err(|_| format!("Invalid slow start window: {}", value))?;
+            slow_start = Some(Duration::from_secs(seconds)).filter(|window| !window.is_zero());
+        } else if let Some(value) = arg.strip_prefix("max_global_conns=") {
+            max_global_conns = Some(value.parse().map_err(|_| format!("Invalid global connection limit: {}", value))?);
+        } else if let Some(value) = arg.strip_prefix("conn_rate=") {
+            // Also accepted with the unit, e.g. conn_rate=20/s
+            let rate = value.trim_end_matches("/sec").trim_end_matches("/s");
+            let rate: u32 = rate.parse().map_err(|_| format!("Invalid connection rate: {}", value))?;
+            conn_rate = Some(rate).filter(|rate| *rate > 0);
+        } else if let Some(value) = arg.strip_prefix("backends=") {
+            for entry in value.split(',').filter(|e| !e.trim().is_empty()) {
+                if let Some(backend) = parse_backend(entry)? {
+                    add_static_backend(&mut backend_groups, &mut host_groups, &mut backend_max_conns, &mut group_tiers, backend)?;
+                }
+            }
+        } else if arg.contains('=') {
+            return Err(format!("Unknown argument: {}", arg));
+        } else if let Some(backend) = parse_backend(arg)? {
+            add_static_backend(&mut backend_groups, &mut host_groups, &mut backend_max_conns, &mut group_tiers, backend)?;
+        }
+    }
+
+    health.jitter = timer_jitter;
+    // A host name group is replaced by each resolution and would drop the group's address backends
+    if let Some(label) = host_groups.keys().find(|label| backend_groups.contains_key(*label)) {
+        return Err(format!("Group {} mixes IP addresses and host names, use a separate group label for each", label));
+    }
+    if health.http_path.is_some() && health.grpc_service.is_some() {
+        return Err("health_http_path and health_grpc cannot be combined, pick one check for TCP backends".to_string());
+    }
+
+    Ok(Config {
+        bind_addrs,
+        backends: backend_groups,
+        host_backends: host_groups,
+        ring_domains,
+        ring_srvs,
+        ring_interval,
+        ring_keep_last,
+        timer_jitter,
+        rdns,
+        rdns_cache_ttl,
+        ip_family,
+        mode,
+        protocols,
+        idle_timeout,
+        proxy_protocol,
+        sni_routing,
+        retries,
+        connect_timeout,
+        no_backend_wait,
+        tcp_pool_size,
+        tcp_pool_idle,
+        access_log,
+        tls_cert,
+        tls_key,
+        udp_session_timeout,
+        udp_batch,
+        udp_max_datagram,
+        udp_max_sessions,
+        health,
+        drain_timeout,
+        drain_state,
+        uds_path,
+        uds_mode,
+        uds_owner,
+        uds_group,
+        uds_token,
+        uds_token_reads,
+        metrics_addr,
+        http_health_addr,
+        log: log_config,
+        max_conns,
+        backend_max_conns,
+        group_tiers,
+        group_modes,
+        rr_scope,
+        max_global_conns,
+        conn_rate,
+        slow_start,
+        workers,
+        bind_retry,
+        reuse_addr,
+        wait_for_health,
+        strict_health,
+        tcp_nodelay,
+        tcp_keepalive,
+        source_addr,
+        adaptive_latency_weight,
+    })
+}
+
+// Resolves once the process receives SIGTERM or SIGINT
+pub async fn shutdown_signal() {
+    let mut sigterm = signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
+    tokio::select! {
+        _ = sigterm.recv() => log("Received SIGTERM".to_string()),
+        _ = tokio::signal::ctrl_c() => log("Received SIGINT".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(args: &[&str]) -> String {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        match parse_arguments(&args) {
+            Ok(_) => panic!("{:?} was accepted", args),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn rejects_missing_bind_address() {
+        assert_eq!(parse_error(&[]), "Missing bind address, the first argument must be <bind_addr:bind_port>");
+        assert_eq!(parse_error(&["mode=random"]), "Missing bind address, the first argument must be <bind_addr:bind_port>");
+    }
+
+    #[test]
+    fn rejects_unparseable_bind_address() {
+        assert_eq!(parse_error(&["localhost:80", "127.0.0.1:9001"]), "Invalid bind address: localhost:80");
+    }
+
+    #[test]
+    fn rejects_invalid_mode() {
+        assert_eq!(parse_error(&["127.0.0.1:8000", "127.0.0.1:9001", "mode=fastest"]), "Invalid load balancer mode: fastest");
+    }
+
+    #[test]
+    fn rejects_invalid_proto() {
+        assert_eq!(parse_error(&["127.0.0.1:8000", "127.0.0.1:9001", "proto=sctp"]), "Invalid protocol: sctp, use tcp, udp or tcp,udp");
+    }
+
+    #[test]
+    fn rejects_invalid_ring_domain_port() {
+        assert_eq!(parse_error(&["127.0.0.1:8000", "ring_domain=db.example.com:99999"]), "Invalid port in ring_domain db.example.com:99999: 99999");
+        assert_eq!(parse_error(&["127.0.0.1:8000", "ring_domain=db.example.com"]), "Missing port in ring_domain db.example.com, use <domain:port>");
+    }
+
+    #[test]
+    fn unescapes_hex_bytes() {
+        assert_eq!(unescape_bytes("\\x00\\x01AB\\xfF").unwrap(), vec![0x00, 0x01, b'A', b'B', 0xff]);
+    }
+
+    #[test]
+    fn unescapes_control_characters() {
+        assert_eq!(unescape_bytes("a\\nb\\rc\\td\\0e\\\\f").unwrap(), b"a\nb\rc\td\0e\\f".to_vec());
+        assert_eq!(unescape_bytes("plain text").unwrap(), b"plain text".to_vec());
+    }
+
+    #[test]
+    fn rejects_truncated_hex_escape() {
+        assert_eq!(unescape_bytes("\\x4").unwrap_err(), "Invalid \\x escape, use two hex digits: \\x4");
+        assert_eq!(unescape_bytes("\\x4g").unwrap_err(), "Invalid \\x escape, use two hex digits: \\x4g");
+        assert_eq!(unescape_bytes("abc\\").unwrap_err(), "Invalid escape at the end of: abc\\");
+    }
+
+    #[test]
+    fn rejects_unknown_escape() {
+        assert_eq!(unescape_bytes("\\q").unwrap_err(), "Invalid escape \\q in: \\q");
+    }
+
+    #[test]
+    fn escape_round_trips() {
+        let bytes = b"\x12\x34\x01\x00\x00\x01\\ok\r\n".to_vec();
+        assert_eq!(unescape_bytes(&escape_bytes(&bytes)).unwrap(), bytes);
+    }
+
+    fn parsed_backends(args: &[&str]) -> Vec<(SocketAddr, Option<Protocol>, u32)> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let config = parse_arguments(&args).unwrap_or_else(|e| panic!("{:?} was rejected: {}", args, e));
+        let mut backends: Vec<_> = config.backends.into_values().flatten().collect();
+        backends.sort_by_key(|(addr, _, _)| *addr);
+        backends
+    }
+
+    #[test]
+    fn accepts_protocol_suffixes() {
+        let backends = parsed_backends(&["127.0.0.1:8000", "10.0.0.1:53/udp", "10.0.0.2:80/TCP", "10.0.0.3:8080"]);
+        assert_eq!(
+            backends,
+            vec![
+                ("10.0.0.1:53".parse().unwrap(), Some(Protocol::UDP), 1),
+                ("10.0.0.2:80".parse().unwrap(), Some(Protocol::TCP), 1),
+                ("10.0.0.3:8080".parse().unwrap(), None, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn accepts_protocol_suffix_with_other_markers() {
+        let backends = parsed_backends(&["127.0.0.1:8000", "backends=10.0.0.1:53*3/udp,[::1]:53/tcp#10"]);
+        assert_eq!(backends, vec![("10.0.0.1:53".parse().unwrap(), Some(Protocol::UDP), 3), ("[::1]:53".parse().unwrap(), Some(Protocol::TCP), 1)]);
+    }
+
+    #[test]
+    fn skips_unknown_protocol_suffix() {
+        let backends = parsed_backends(&["127.0.0.1:8000", "10.0.0.1:53/sctp", "10.0.0.2:53/", "10.0.0.3:53/udp"]);
+        assert_eq!(backends, vec![("10.0.0.3:53".parse().unwrap(), Some(Protocol::UDP), 1)]);
+    }
+
+    #[test]
+    fn rejects_invalid_address_before_suffix() {
+        assert_eq!(parse_error(&["127.0.0.1:8000", "10.0.0.1/udp"]), "Invalid backend address: 10.0.0.1/udp");
+    }
 }